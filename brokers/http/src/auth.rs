@@ -0,0 +1,270 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use hmac::{Hmac, Mac};
+use reqwest::{
+	header::{HeaderMap, HeaderValue, AUTHORIZATION},
+	RequestBuilder,
+};
+use sha2::Sha256;
+use thiserror::Error;
+
+use crate::options::{Opt, Secret};
+
+pub const TIMESTAMP_HEADER: &str = "x-spectacles-timestamp";
+pub const SIGNATURE_HEADER: &str = "x-spectacles-signature";
+
+const SIGNATURE_PREFIX: &str = "sha256=";
+
+#[derive(Debug, Error)]
+pub enum AuthError {
+	#[error("missing or invalid bearer token")]
+	Token,
+	#[error("missing or malformed signature headers")]
+	MissingSignature,
+	#[error("signature timestamp is outside of the allowed window")]
+	Expired,
+	#[error("signature does not match")]
+	Signature,
+}
+
+/// Authenticates requests between brokers with a shared bearer token and/or HMAC-SHA256
+/// signatures.
+///
+/// Signatures cover a Unix timestamp, the event name and the body, so that a captured request
+/// can't be replayed outside of the allowed window or as a different event.
+#[derive(Debug, Clone)]
+pub struct Auth {
+	token: Option<Secret>,
+	secret: Option<Secret>,
+	max_age: Duration,
+}
+
+impl From<&Opt> for Auth {
+	fn from(opt: &Opt) -> Self {
+		Self::new(
+			opt.token.clone(),
+			opt.hmac_secret.clone(),
+			opt.max_signature_age,
+		)
+	}
+}
+
+impl Auth {
+	pub fn new(token: Option<Secret>, secret: Option<Secret>, max_age: Duration) -> Self {
+		Self {
+			token,
+			secret,
+			max_age,
+		}
+	}
+
+	/// Add credentials for an event named `name` with `body` to `request`.
+	pub fn sign(&self, request: RequestBuilder, name: &str, body: &[u8]) -> RequestBuilder {
+		request.headers(self.headers(name, body))
+	}
+
+	/// The headers that carry credentials for an event named `name` with `body`.
+	pub fn headers(&self, name: &str, body: &[u8]) -> HeaderMap {
+		let mut headers = HeaderMap::new();
+
+		if let Some(token) = &self.token {
+			if let Ok(value) = HeaderValue::try_from(format!("Bearer {}", token.expose())) {
+				headers.insert(AUTHORIZATION, value);
+			}
+		}
+
+		if let Some(secret) = &self.secret {
+			let timestamp = unix_timestamp(SystemTime::now());
+			let signature = hex::encode(mac(secret, timestamp, name, body).finalize().into_bytes());
+
+			headers.insert(TIMESTAMP_HEADER, timestamp.into());
+			if let Ok(value) = HeaderValue::try_from(format!("{}{}", SIGNATURE_PREFIX, signature)) {
+				headers.insert(SIGNATURE_HEADER, value);
+			}
+		}
+
+		headers
+	}
+
+	/// Verify the credentials of a request for an event named `name` with `body`.
+	pub fn verify(&self, headers: &HeaderMap, name: &str, body: &[u8]) -> Result<(), AuthError> {
+		if let Some(token) = &self.token {
+			let provided = headers
+				.get(AUTHORIZATION)
+				.and_then(|value| value.to_str().ok())
+				.and_then(|value| value.strip_prefix("Bearer "))
+				.ok_or(AuthError::Token)?;
+
+			if !constant_time_eq(provided.as_bytes(), token.expose().as_bytes()) {
+				return Err(AuthError::Token);
+			}
+		}
+
+		if let Some(secret) = &self.secret {
+			let timestamp = headers
+				.get(TIMESTAMP_HEADER)
+				.and_then(|value| value.to_str().ok()?.parse::<u64>().ok())
+				.ok_or(AuthError::MissingSignature)?;
+
+			let signature = headers
+				.get(SIGNATURE_HEADER)
+				.and_then(|value| value.to_str().ok()?.strip_prefix(SIGNATURE_PREFIX))
+				.and_then(|value| hex::decode(value).ok())
+				.ok_or(AuthError::MissingSignature)?;
+
+			let now = unix_timestamp(SystemTime::now());
+			if now.abs_diff(timestamp) > self.max_age.as_secs() {
+				return Err(AuthError::Expired);
+			}
+
+			mac(secret, timestamp, name, body)
+				.verify_slice(&signature)
+				.map_err(|_| AuthError::Signature)?;
+		}
+
+		Ok(())
+	}
+}
+
+fn mac(secret: &Secret, timestamp: u64, name: &str, body: &[u8]) -> Hmac<Sha256> {
+	let mut mac = Hmac::<Sha256>::new_from_slice(secret.expose().as_bytes())
+		.expect("HMAC accepts keys of any size");
+	mac.update(format!("{}.{}.", timestamp, name).as_bytes());
+	mac.update(body);
+	mac
+}
+
+fn unix_timestamp(time: SystemTime) -> u64 {
+	time.duration_since(UNIX_EPOCH)
+		.unwrap_or_default()
+		.as_secs()
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+	a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const BODY: &[u8] = b"\x81\xa2id\x01";
+
+	fn auth(token: Option<&str>, secret: Option<&str>) -> Auth {
+		Auth::new(
+			token.map(|token| token.parse().unwrap()),
+			secret.map(|secret| secret.parse().unwrap()),
+			Duration::from_secs(300),
+		)
+	}
+
+	/// Headers signed for `name` and `body` at `timestamp`.
+	fn signed_at(secret: &str, timestamp: u64, name: &str, body: &[u8]) -> HeaderMap {
+		let secret = secret.parse().unwrap();
+		let signature = hex::encode(mac(&secret, timestamp, name, body).finalize().into_bytes());
+
+		let mut headers = HeaderMap::new();
+		headers.insert(TIMESTAMP_HEADER, timestamp.into());
+		headers.insert(
+			SIGNATURE_HEADER,
+			HeaderValue::try_from(format!("{}{}", SIGNATURE_PREFIX, signature)).unwrap(),
+		);
+		headers
+	}
+
+	#[test]
+	fn valid_signature() {
+		let auth = auth(None, Some("secret"));
+		let headers = auth.headers("MESSAGE_CREATE", BODY);
+		assert!(auth.verify(&headers, "MESSAGE_CREATE", BODY).is_ok());
+	}
+
+	#[test]
+	fn wrong_signature() {
+		let auth = auth(None, Some("secret"));
+		let now = unix_timestamp(SystemTime::now());
+
+		let headers = signed_at("other secret", now, "MESSAGE_CREATE", BODY);
+		assert!(matches!(
+			auth.verify(&headers, "MESSAGE_CREATE", BODY),
+			Err(AuthError::Signature)
+		));
+
+		// A signature for another event or body doesn't match either.
+		let headers = auth.headers("MESSAGE_CREATE", BODY);
+		assert!(matches!(
+			auth.verify(&headers, "MESSAGE_DELETE", BODY),
+			Err(AuthError::Signature)
+		));
+		assert!(matches!(
+			auth.verify(&headers, "MESSAGE_CREATE", b"\xc0"),
+			Err(AuthError::Signature)
+		));
+	}
+
+	#[test]
+	fn stale_timestamp() {
+		let auth = auth(None, Some("secret"));
+		let now = unix_timestamp(SystemTime::now());
+
+		for timestamp in [now - 301, now + 301] {
+			let headers = signed_at("secret", timestamp, "MESSAGE_CREATE", BODY);
+			assert!(matches!(
+				auth.verify(&headers, "MESSAGE_CREATE", BODY),
+				Err(AuthError::Expired)
+			));
+		}
+
+		let headers = signed_at("secret", now - 290, "MESSAGE_CREATE", BODY);
+		assert!(auth.verify(&headers, "MESSAGE_CREATE", BODY).is_ok());
+	}
+
+	#[test]
+	fn missing_signature() {
+		let auth = auth(None, Some("secret"));
+		assert!(matches!(
+			auth.verify(&HeaderMap::new(), "MESSAGE_CREATE", BODY),
+			Err(AuthError::MissingSignature)
+		));
+
+		let mut headers = auth.headers("MESSAGE_CREATE", BODY);
+		headers.remove(TIMESTAMP_HEADER);
+		assert!(matches!(
+			auth.verify(&headers, "MESSAGE_CREATE", BODY),
+			Err(AuthError::MissingSignature)
+		));
+
+		let mut headers = auth.headers("MESSAGE_CREATE", BODY);
+		headers.insert(SIGNATURE_HEADER, HeaderValue::from_static("sha256=nothex"));
+		assert!(matches!(
+			auth.verify(&headers, "MESSAGE_CREATE", BODY),
+			Err(AuthError::MissingSignature)
+		));
+	}
+
+	#[test]
+	fn token() {
+		let auth = auth(Some("token"), None);
+		let headers = auth.headers("MESSAGE_CREATE", BODY);
+		assert!(auth.verify(&headers, "MESSAGE_CREATE", BODY).is_ok());
+
+		let mut headers = HeaderMap::new();
+		assert!(matches!(
+			auth.verify(&headers, "MESSAGE_CREATE", BODY),
+			Err(AuthError::Token)
+		));
+
+		headers.insert(AUTHORIZATION, HeaderValue::from_static("Bearer wrong"));
+		assert!(matches!(
+			auth.verify(&headers, "MESSAGE_CREATE", BODY),
+			Err(AuthError::Token)
+		));
+	}
+
+	#[test]
+	fn secrets_are_redacted() {
+		let debug = format!("{:?}", auth(Some("hunter2"), Some("swordfish")));
+		assert!(!debug.contains("hunter2"));
+		assert!(!debug.contains("swordfish"));
+	}
+}
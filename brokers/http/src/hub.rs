@@ -0,0 +1,52 @@
+use std::sync::Arc;
+
+use spectacles::AnyEvent;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tracing::warn;
+
+/// Broadcasts events read from STDIN to every connected subscriber.
+#[derive(Debug, Clone)]
+pub struct Hub {
+	tx: broadcast::Sender<Arc<AnyEvent>>,
+}
+
+impl Hub {
+	/// Create a hub that buffers up to `capacity` events for each subscriber.
+	pub fn new(capacity: usize) -> Self {
+		let (tx, _) = broadcast::channel(capacity.max(1));
+		Self { tx }
+	}
+
+	pub fn publish(&self, event: &AnyEvent) {
+		if self.tx.receiver_count() > 0 {
+			let _ = self.tx.send(Arc::new(event.clone()));
+		}
+	}
+
+	pub fn subscribe(&self) -> Subscriber {
+		Subscriber {
+			rx: self.tx.subscribe(),
+		}
+	}
+}
+
+#[derive(Debug)]
+pub struct Subscriber {
+	rx: broadcast::Receiver<Arc<AnyEvent>>,
+}
+
+impl Subscriber {
+	/// Receive the next event. A subscriber that falls too far behind skips the events it missed
+	/// rather than holding up the others.
+	pub async fn recv(&mut self) -> Option<Arc<AnyEvent>> {
+		loop {
+			match self.rx.recv().await {
+				Ok(event) => return Some(event),
+				Err(RecvError::Lagged(skipped)) => {
+					warn!(skipped, "Subscriber fell behind, skipping events")
+				}
+				Err(RecvError::Closed) => return None,
+			}
+		}
+	}
+}
@@ -0,0 +1,194 @@
+use std::sync::Arc;
+
+use anyhow::{anyhow, Context};
+use axum::{
+	extract::Path,
+	http::HeaderMap,
+	response::{IntoResponse, Response},
+	routing::{get, on, post},
+	Extension, Router, Server,
+};
+use axum_server::tls_rustls::RustlsConfig;
+use bytes::Bytes;
+use reqwest::StatusCode;
+use spectacles::{admin, from_slice, metrics::metrics, to_vec, trace, AnyEvent, EventRef, Value};
+use tracing::{debug, info, info_span, warn, Instrument};
+
+use crate::{
+	auth::Auth,
+	hub::Hub,
+	interactions::{handle_interaction, Interactions},
+	options::Opt,
+	output::{Output, WriteError},
+	replies::Replies,
+	sse, tls, websocket,
+};
+
+/// Handle a single event, writing it to STDOUT. When replies are enabled, the request is held open
+/// until a reply to the event is read from STDIN, and responded to with the reply's data.
+async fn handle_request(
+	Path(path): Path<String>,
+	Extension(auth): Extension<Arc<Auth>>,
+	Extension(replies): Extension<Option<Arc<Replies>>>,
+	Extension(output): Extension<Output>,
+	headers: HeaderMap,
+	body: Bytes,
+) -> Result<Response, StatusCode> {
+	if let Err(err) = auth.verify(&headers, &path, &body) {
+		warn!(%err, event = %path, "Rejected unauthenticated request");
+		return Err(StatusCode::UNAUTHORIZED);
+	}
+
+	let data = from_slice::<Value>(&body).map_err(|_| StatusCode::BAD_REQUEST)?;
+
+	let traceparent = headers
+		.get(trace::TRACEPARENT)
+		.and_then(|value| value.to_str().ok());
+	let span = info_span!("receive", event = %path);
+	trace::set_parent(&span, traceparent);
+
+	async {
+		let traceparent = trace::forward(traceparent);
+		let mut pending = replies.map(|replies| replies.wait());
+		let event = EventRef {
+			id: pending.as_ref().map(|pending| pending.id.as_str()),
+			traceparent: traceparent.as_deref(),
+			..EventRef::new(&path, data)
+		};
+		debug!(?event);
+
+		output.write(&event).await.map_err(rejected)?;
+		metrics().event_out(event.name);
+
+		let pending = match &mut pending {
+			Some(pending) => pending,
+			None => return Ok(StatusCode::NO_CONTENT.into_response()),
+		};
+
+		match pending.recv().await {
+			Some(reply) => {
+				debug!(?reply);
+				let bytes = to_vec(&reply.data).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+				Ok(bytes.into_response())
+			}
+			None => {
+				warn!(event = %path, id = %pending.id, "Timed out waiting for reply");
+				Err(StatusCode::GATEWAY_TIMEOUT)
+			}
+		}
+	}
+	.instrument(span)
+	.await
+}
+
+/// Handle a batch of events sent to the root of the URL, writing them to STDOUT in order.
+async fn handle_batch(
+	Extension(auth): Extension<Arc<Auth>>,
+	Extension(output): Extension<Output>,
+	headers: HeaderMap,
+	body: Bytes,
+) -> Result<StatusCode, StatusCode> {
+	if let Err(err) = auth.verify(&headers, "", &body) {
+		warn!(%err, "Rejected unauthenticated batch");
+		return Err(StatusCode::UNAUTHORIZED);
+	}
+
+	let events = from_slice::<Vec<AnyEvent>>(&body).map_err(|_| StatusCode::BAD_REQUEST)?;
+	debug!(?events);
+
+	output.write_all(&events).await.map_err(rejected)?;
+	for event in &events {
+		metrics().event_out(&event.name);
+	}
+
+	Ok(StatusCode::NO_CONTENT)
+}
+
+/// Log that an incoming event couldn't be written to STDOUT, and respond accordingly.
+pub fn rejected(err: WriteError) -> StatusCode {
+	warn!(%err, "Unable to write incoming event");
+	err.status()
+}
+
+pub async fn handle_http_in(
+	opt: Opt,
+	output: Output,
+	replies: Option<Arc<Replies>>,
+	hub: Option<Hub>,
+) -> anyhow::Result<()> {
+	let auth = Arc::new(Auth::from(&opt));
+
+	let base = opt.url.path().trim_end_matches('/');
+	let filter = opt
+		.method
+		.clone()
+		.try_into()
+		.map_err(|_| anyhow!("unable to receive {} requests", opt.method))?;
+	let mut app = Router::new()
+		.route(&format!("{}/:name", base), on(filter, handle_request))
+		.route(opt.url.path(), on(filter, handle_batch));
+
+	if let Some(hub) = hub {
+		if opt.websocket {
+			app = app.route(&format!("{}/ws", base), get(websocket::handle_upgrade));
+		}
+		if opt.sse {
+			app = app.route(&format!("{}/events", base), get(sse::handle_sse));
+		}
+		app = app.layer(Extension(hub));
+	}
+
+	if let (Some(public_key), Some(replies)) = (opt.discord_public_key, &replies) {
+		let interactions = Interactions {
+			public_key,
+			replies: replies.clone(),
+			timeout: opt.reply_timeout,
+		};
+
+		app = app
+			.route(&format!("{}/interactions", base), post(handle_interaction))
+			.layer(Extension(Arc::new(interactions)));
+	}
+
+	// Replies may only be enabled for interactions, in which case other requests aren't held.
+	let replies = replies.filter(|_| opt.replies);
+	let app = app
+		.layer(Extension(auth))
+		.layer(Extension(replies))
+		.layer(Extension(output));
+
+	let addr = opt
+		.url
+		.socket_addrs(|| None)
+		.with_context(|| format!("unable to resolve {}", opt.url))?
+		.into_iter()
+		.next()
+		.with_context(|| format!("{} has no addresses to listen on", opt.url))?;
+
+	let status = admin::component("http");
+	if opt.url.scheme() == "https" {
+		let config = RustlsConfig::from_config(Arc::new(tls::load_config(&opt).await?));
+		let server = axum_server::bind_rustls(addr, config.clone());
+
+		info!("Listening on {}", opt.url);
+		status.up();
+		tokio::spawn(tls::reload_on_change(config, opt));
+
+		server
+			.serve(app.into_make_service())
+			.await
+			.context("HTTPS server failed")?;
+	} else {
+		let server =
+			Server::try_bind(&addr).with_context(|| format!("unable to listen on {}", addr))?;
+
+		info!("Listening on {}", opt.url);
+		status.up();
+		server
+			.serve(app.into_make_service())
+			.await
+			.context("HTTP server failed")?;
+	}
+
+	Ok(())
+}
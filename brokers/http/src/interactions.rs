@@ -0,0 +1,114 @@
+use std::{sync::Arc, time::Duration};
+
+use axum::{http::HeaderMap, response::IntoResponse, Extension, Json};
+use bytes::Bytes;
+use ed25519_dalek::{Signature, Verifier, VerifyingKey};
+use reqwest::StatusCode;
+use spectacles::{metrics::metrics, trace, EventRef, Value};
+use tracing::{debug, info_span, warn, Instrument};
+
+use crate::{inbound::rejected, output::Output, replies::Replies};
+
+const SIGNATURE_HEADER: &str = "x-signature-ed25519";
+const TIMESTAMP_HEADER: &str = "x-signature-timestamp";
+
+/// Discord fails interactions that aren't responded to within 3 seconds. Leave some of that for
+/// the response to reach it.
+const RESPONSE_WINDOW: Duration = Duration::from_millis(2500);
+
+const PING: u64 = 1;
+const PONG: u64 = 1;
+
+/// Receives interactions from Discord's outgoing webhook.
+#[derive(Debug)]
+pub struct Interactions {
+	pub public_key: VerifyingKey,
+	pub replies: Arc<Replies>,
+	pub timeout: Duration,
+}
+
+/// Parse the hex-encoded public key of a Discord application.
+pub fn parse_public_key(key: &str) -> anyhow::Result<VerifyingKey> {
+	let bytes = hex::decode(key)?
+		.try_into()
+		.map_err(|_| anyhow::anyhow!("public key must be 32 bytes"))?;
+
+	Ok(VerifyingKey::from_bytes(&bytes)?)
+}
+
+impl Interactions {
+	/// Verify that `body` was signed by Discord.
+	fn verify(&self, headers: &HeaderMap, body: &[u8]) -> bool {
+		let signature = headers
+			.get(SIGNATURE_HEADER)
+			.and_then(|value| hex::decode(value.as_bytes()).ok())
+			.and_then(|bytes| Signature::from_slice(&bytes).ok());
+		let timestamp = headers.get(TIMESTAMP_HEADER);
+
+		let (signature, timestamp) = match (signature, timestamp) {
+			(Some(signature), Some(timestamp)) => (signature, timestamp),
+			_ => return false,
+		};
+
+		let message = [timestamp.as_bytes(), body].concat();
+		self.public_key.verify(&message, &signature).is_ok()
+	}
+}
+
+/// Handle an interaction, answering PINGs and otherwise writing an `INTERACTION_CREATE` event to
+/// STDOUT. The interaction is responded to with the data of the reply to that event.
+pub async fn handle_interaction(
+	Extension(interactions): Extension<Arc<Interactions>>,
+	Extension(output): Extension<Output>,
+	headers: HeaderMap,
+	body: Bytes,
+) -> Result<impl IntoResponse, StatusCode> {
+	if !interactions.verify(&headers, &body) {
+		warn!("Rejected interaction with invalid signature");
+		return Err(StatusCode::UNAUTHORIZED);
+	}
+
+	let data = serde_json::from_slice::<Value>(&body).map_err(|_| StatusCode::BAD_REQUEST)?;
+	let kind = data
+		.as_map()
+		.and_then(|map| map.iter().find(|(key, _)| key.as_str() == Some("type")))
+		.and_then(|(_, kind)| kind.as_u64());
+
+	if kind == Some(PING) {
+		let pong = Value::Map(vec![(Value::from("type"), Value::from(PONG))]);
+		return Ok(Json(pong));
+	}
+
+	// Interactions start a trace of their own, continued by whatever handles them.
+	let span = info_span!("interaction");
+
+	async {
+		let traceparent = trace::traceparent();
+		let mut pending = interactions.replies.wait();
+		let event = EventRef {
+			id: Some(pending.id.as_str()),
+			traceparent: traceparent.as_deref(),
+			..EventRef::new("INTERACTION_CREATE", data)
+		};
+		debug!(?event);
+
+		output.write(&event).await.map_err(rejected)?;
+		metrics().event_out(event.name);
+
+		match pending
+			.recv_within(interactions.timeout.min(RESPONSE_WINDOW))
+			.await
+		{
+			Some(reply) => {
+				debug!(?reply);
+				Ok(Json(reply.data))
+			}
+			None => {
+				warn!(id = %pending.id, "Timed out waiting for interaction response");
+				Err(StatusCode::GATEWAY_TIMEOUT)
+			}
+		}
+	}
+	.instrument(span)
+	.await
+}
@@ -1,95 +1,101 @@
-use std::io::{stdout, Write};
+use std::{future::ready, sync::Arc};
 
-use axum::{extract::Path, routing::on, Router, Server};
-use bytes::Bytes;
-use futures::StreamExt;
+use futures::{Stream, StreamExt};
+use hub::Hub;
+use inbound::handle_http_in;
 use options::Opt;
-use reqwest::{Client, StatusCode};
-use spectacles::{from_slice, init_tracing, io::read, to_vec, AnyEvent, EventRef, Value};
+use outbound::handle_http_out;
+use output::Output;
+use replies::Replies;
+use spectacles::{admin, init_tracing, io::read, metrics::metrics, AnyEvent};
 use structopt::StructOpt;
 use tokio::task::JoinSet;
-use tracing::{debug, info, info_span, warn, Instrument};
+use tracing::{debug, info};
+use websocket::handle_ws_out;
 
+mod auth;
+mod hub;
+mod inbound;
+mod interactions;
 mod options;
-
-async fn handle_http_out(opt: Opt) -> anyhow::Result<()> {
-	let client = Client::new();
-	let mut rd = read::<AnyEvent>();
-
-	let mut set = JoinSet::new();
-
-	while let Some(event) = rd.next().await {
-		let data = to_vec(&event.data)?;
-		let client = client.clone();
-		let opt = opt.clone();
-		let span = info_span!("make_request", ?event);
-
-		set.spawn(
-			async move {
-				let result = client
-					.request(opt.method, format!("{}{}", opt.url, event.name))
-					.body(data)
-					.send()
-					.await;
-
-				match result {
-					Ok(response) => debug!(?response),
-					Err(err) => warn!(%err),
-				}
-			}
-			.instrument(span),
-		);
-	}
-
-	while set.join_next().await.is_some() {}
-	Ok(())
-}
-
-async fn handle_http_in(opt: Opt) -> anyhow::Result<()> {
-	async fn handle_request(
-		Path(path): Path<String>,
-		body: Bytes,
-	) -> Result<StatusCode, StatusCode> {
-		let data = from_slice::<Value>(&body).map_err(|_| StatusCode::BAD_REQUEST)?;
-		let event = EventRef { data, name: &path };
-		debug!(?event);
-
-		let bytes = to_vec(&event).unwrap();
-		stdout().write_all(&bytes).unwrap();
-
-		Ok(StatusCode::NO_CONTENT)
-	}
-
-	let app = Router::new().route(
-		&format!("{}/:name", opt.url.path()),
-		on(opt.method.try_into().unwrap(), handle_request),
-	);
-
-	info!("Listening on {}", opt.url);
-	Server::bind(&opt.url.socket_addrs(|| None).unwrap()[0])
-		.serve(app.into_make_service())
-		.await
-		.unwrap();
-
-	Ok(())
-}
+mod outbound;
+mod outbox;
+mod output;
+mod replies;
+mod retry;
+mod sse;
+mod tls;
+mod websocket;
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-	init_tracing();
+	let _tracing = init_tracing();
 
 	let opt = Opt::from_args();
 	info!(?opt);
 
+	if let Some(addr) = opt.admin_addr {
+		admin::spawn(addr);
+	}
+
+	let replies = (opt.r#in && (opt.replies || opt.discord_public_key.is_some()))
+		.then(|| Arc::new(Replies::new(opt.reply_timeout)));
+	let hub = (opt.r#in && (opt.websocket || opt.sse)).then(|| Hub::new(opt.broadcast_capacity));
+
+	let output = Output::spawn();
+
 	let mut set = JoinSet::new();
-	if !opt.r#in || opt.out {
-		set.spawn(handle_http_out(opt.clone()));
+	if !opt.r#in {
+		let events = read_events(None, None);
+		if opt.websocket {
+			set.spawn(handle_ws_out(opt.clone(), output.clone(), events));
+		} else {
+			set.spawn(handle_http_out(opt.clone(), output.clone(), events));
+		}
+	} else if opt.out {
+		let events = read_events(replies.clone(), hub.clone());
+		set.spawn(handle_http_out(opt.clone(), output.clone(), events));
+	} else if replies.is_some() || hub.is_some() {
+		let events = read_events(replies.clone(), hub.clone());
+		set.spawn(async move {
+			events
+				.for_each(|event| {
+					debug!(?event, "Ignoring event");
+					ready(())
+				})
+				.await;
+			Ok(())
+		});
 	}
 
 	if opt.r#in {
-		set.spawn(handle_http_in(opt));
+		set.spawn(handle_http_in(opt, output, replies, hub));
+	}
+
+	while let Some(result) = set.join_next().await {
+		result??;
 	}
 
-	while set.join_next().await.is_some() {}
 	Ok(())
 }
+
+/// Read events to send from STDIN, handing replies to incoming requests to `replies` and
+/// broadcasting the rest to `hub`.
+fn read_events(
+	replies: Option<Arc<Replies>>,
+	hub: Option<Hub>,
+) -> impl Stream<Item = AnyEvent> + Unpin {
+	read::<AnyEvent>().filter_map(move |event| {
+		metrics().event_in(&event.name);
+		let event = match &replies {
+			Some(replies) => replies.resolve(event),
+			None => Some(event),
+		};
+
+		if let (Some(hub), Some(event)) = (&hub, &event) {
+			hub.publish(event);
+		}
+
+		ready(event)
+	})
+}
@@ -1,5 +1,19 @@
+use std::{
+	convert::Infallible,
+	fmt::{self, Debug, Formatter},
+	net::SocketAddr,
+	path::PathBuf,
+	str::FromStr,
+	time::Duration,
+};
+
+use ed25519_dalek::VerifyingKey;
+use humantime::parse_duration;
 use reqwest::{Method, Url};
 use structopt::StructOpt;
+use tokio::sync::Semaphore;
+
+use crate::interactions::parse_public_key;
 
 #[derive(Debug, Clone, StructOpt)]
 pub struct Opt {
@@ -11,4 +25,226 @@ pub struct Opt {
 	pub r#in: bool,
 	#[structopt(long)]
 	pub out: bool,
+
+	/// The maximum number of attempts to deliver each outgoing event.
+	#[structopt(long, default_value = "5")]
+	pub max_attempts: u32,
+	/// The maximum delay before the first retry. The maximum doubles after each attempt, and the
+	/// actual delay is chosen randomly up to it.
+	#[structopt(long, default_value = "100ms", parse(try_from_str = parse_duration))]
+	pub initial_backoff: Duration,
+	/// The upper limit of the delay between retries, including delays requested by `Retry-After`.
+	#[structopt(long, default_value = "30s", parse(try_from_str = parse_duration))]
+	pub max_backoff: Duration,
+	/// A directory to persist outgoing events in until they are delivered. Events that could not
+	/// be delivered, including those left from previous runs, are periodically retried.
+	#[structopt(long)]
+	pub outbox: Option<PathBuf>,
+	/// How often to retry delivering events left in the outbox.
+	#[structopt(long, default_value = "30s", parse(try_from_str = parse_duration))]
+	pub outbox_interval: Duration,
+
+	/// A shared secret sent as a bearer token with outgoing requests and required of incoming
+	/// requests.
+	#[structopt(long, env = "HTTP_TOKEN", hide_env_values = true)]
+	pub token: Option<Secret>,
+	/// A shared secret used to sign outgoing requests with HMAC-SHA256 and to verify the
+	/// signatures of incoming requests.
+	#[structopt(long, env = "HTTP_HMAC_SECRET", hide_env_values = true)]
+	pub hmac_secret: Option<Secret>,
+	/// How far the timestamp of a signed incoming request may be from the current time.
+	#[structopt(long, default_value = "5m", parse(try_from_str = parse_duration))]
+	pub max_signature_age: Duration,
+
+	/// Path to the PEM certificate chain to serve HTTPS with, when the URL scheme is `https`.
+	#[structopt(long, env = "HTTP_TLS_CERT")]
+	pub tls_cert: Option<PathBuf>,
+	/// Path to the PEM private key of the certificate.
+	#[structopt(long, env = "HTTP_TLS_KEY")]
+	pub tls_key: Option<PathBuf>,
+	/// Path to PEM CA certificates to verify client certificates with. When set, incoming
+	/// connections must present a certificate signed by one of them.
+	#[structopt(long, env = "HTTP_TLS_CLIENT_CA")]
+	pub tls_client_ca: Option<PathBuf>,
+	/// How often to check the TLS files for changes, reloading them without a restart.
+	#[structopt(long, default_value = "1m", parse(try_from_str = parse_duration))]
+	pub tls_reload_interval: Duration,
+
+	/// The maximum number of outgoing events to handle at once, including redeliveries from the
+	/// outbox. Once reached, no more events are read from STDIN until one completes.
+	#[structopt(long, default_value = "64", parse(try_from_str = parse_max_in_flight))]
+	pub max_in_flight: usize,
+	/// Deliver outgoing events one at a time per event name (`event`) or per guild (`guild`),
+	/// in the order they were received. Events without a guild are not ordered.
+	#[structopt(long, default_value = "none", possible_values = &OrderBy::VARIANTS)]
+	pub order_by: OrderBy,
+	/// Send outgoing events in batches, as a single MessagePack array of events to the URL
+	/// itself. Batches are sent once any of the batch limits is reached.
+	#[structopt(long)]
+	pub batch: bool,
+	/// The maximum number of events in a batch.
+	#[structopt(long, default_value = "100")]
+	pub batch_max_events: usize,
+	/// The approximate maximum size of a batch, in bytes.
+	#[structopt(long, default_value = "1048576")]
+	pub batch_max_bytes: usize,
+	/// The maximum time to wait for more events before sending a batch.
+	#[structopt(long, default_value = "100ms", parse(try_from_str = parse_duration))]
+	pub batch_max_delay: Duration,
+	/// Write the responses to outgoing events to STDOUT as replies, and hold incoming requests open
+	/// until a reply to their event is read from STDIN. Replies refer to the `id` of the event they
+	/// reply to in their `reply_to`, so outgoing events without an `id` aren't replied to.
+	#[structopt(long)]
+	pub replies: bool,
+	/// How long to hold an incoming request open waiting for a reply before responding with
+	/// `504 Gateway Timeout`.
+	#[structopt(long, default_value = "3s", parse(try_from_str = parse_duration))]
+	pub reply_timeout: Duration,
+	/// Exchange events over a WebSocket. Inbound, the server also accepts WebSocket connections at
+	/// `/ws` and streams the events read from STDIN to them. Otherwise, the broker keeps a
+	/// connection open to the URL, which should have the `ws` or `wss` scheme, instead of making a
+	/// request for each event.
+	#[structopt(long)]
+	pub websocket: bool,
+	/// The names of the events to receive over the WebSocket, or `*` to receive every event.
+	#[structopt(long = "subscribe", short = "s", number_of_values = 1)]
+	pub subscriptions: Vec<String>,
+	/// Serve the events read from STDIN as Server-Sent Events at `/events`, encoded as JSON. The
+	/// `events` query parameter takes a comma-separated list of the event names to receive. Since
+	/// `EventSource` can't set headers, the token may be given as the `token` query parameter, and
+	/// a signature of an empty event name and body as the `timestamp` and `signature` ones.
+	#[structopt(long)]
+	pub sse: bool,
+	/// The public key of a Discord application, to receive its interactions at `/interactions`.
+	/// Interactions are written to STDOUT as `INTERACTION_CREATE` events, and are responded to
+	/// with the reply to that event, which must arrive within Discord's 3 second limit. Requires
+	/// `--in`.
+	#[structopt(
+		long,
+		env = "DISCORD_PUBLIC_KEY",
+		requires = "in",
+		parse(try_from_str = parse_public_key)
+	)]
+	pub discord_public_key: Option<VerifyingKey>,
+	/// How many events to buffer for each WebSocket client or SSE subscriber. Those that fall
+	/// further behind skip the events they missed.
+	#[structopt(long, default_value = "1024")]
+	pub broadcast_capacity: usize,
+	/// The address to serve health checks and metrics on.
+	#[structopt(long, env = "HTTP_ADMIN_ADDR")]
+	pub admin_addr: Option<SocketAddr>,
+}
+
+/// A secret option, which is left out of debug output so that it isn't logged.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Secret(String);
+
+impl Secret {
+	pub fn expose(&self) -> &str {
+		&self.0
+	}
+}
+
+impl Debug for Secret {
+	fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+		f.write_str("[redacted]")
+	}
+}
+
+impl FromStr for Secret {
+	type Err = Infallible;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		Ok(Self(s.to_string()))
+	}
+}
+
+/// The largest number of outgoing events that can be in flight: the most permits a semaphore can
+/// hold, and that can be acquired at once when waiting for every event to complete.
+const MAX_IN_FLIGHT: usize = if Semaphore::MAX_PERMITS < u32::MAX as usize {
+	Semaphore::MAX_PERMITS
+} else {
+	u32::MAX as usize
+};
+
+fn parse_max_in_flight(s: &str) -> Result<usize, String> {
+	match s.parse() {
+		Ok(max @ 1..=MAX_IN_FLIGHT) => Ok(max),
+		_ => Err(format!("expected a number from 1 to {}", MAX_IN_FLIGHT)),
+	}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OrderBy {
+	None,
+	Event,
+	Guild,
+}
+
+impl OrderBy {
+	pub const VARIANTS: [&'static str; 3] = ["none", "event", "guild"];
+}
+
+impl FromStr for OrderBy {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s {
+			"none" => Ok(Self::None),
+			"event" => Ok(Self::Event),
+			"guild" => Ok(Self::Guild),
+			s => Err(format!("invalid order: {}", s)),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn secrets_are_redacted() {
+		let opt = Opt::from_iter([
+			"spectacles-http",
+			"http://localhost/",
+			"--token",
+			"hunter2",
+			"--hmac-secret",
+			"swordfish",
+		]);
+
+		let debug = format!("{:?}", opt);
+		assert!(!debug.contains("hunter2"));
+		assert!(!debug.contains("swordfish"));
+		assert_eq!(opt.token.unwrap().expose(), "hunter2");
+	}
+
+	#[test]
+	fn max_in_flight() {
+		assert_eq!(parse_max_in_flight("1"), Ok(1));
+		assert_eq!(parse_max_in_flight("64"), Ok(64));
+		assert_eq!(
+			parse_max_in_flight(&MAX_IN_FLIGHT.to_string()),
+			Ok(MAX_IN_FLIGHT)
+		);
+
+		assert!(parse_max_in_flight("0").is_err());
+		assert!(parse_max_in_flight(&(MAX_IN_FLIGHT + 1).to_string()).is_err());
+		assert!(parse_max_in_flight("many").is_err());
+	}
+
+	#[test]
+	fn discord_public_key_requires_in() {
+		// The encoding of the identity point, which is a valid public key.
+		let key = format!("01{}", "00".repeat(31));
+		let args = [
+			"spectacles-http",
+			"http://localhost/",
+			"--discord-public-key",
+			&key,
+		];
+
+		assert!(Opt::from_iter_safe(args).is_err());
+		assert!(Opt::from_iter_safe(args.iter().chain(&["--in"])).is_ok());
+	}
 }
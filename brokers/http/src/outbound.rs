@@ -0,0 +1,462 @@
+use std::{
+	collections::HashMap,
+	path::PathBuf,
+	sync::{Arc, Mutex},
+	time::Duration,
+};
+
+use bytes::Bytes;
+use futures::{
+	future::{join_all, Shared},
+	FutureExt, Stream, StreamExt,
+};
+use reqwest::Client;
+use spectacles::{from_slice, metrics::metrics, to_vec, trace, AnyEvent, Event, Value};
+use tokio::{
+	sync::{oneshot, OwnedSemaphorePermit, Semaphore},
+	time::{interval, timeout_at, Instant},
+};
+use tracing::{debug, info_span, warn, Instrument};
+
+use crate::{
+	auth::Auth,
+	options::{Opt, OrderBy},
+	outbox::Outbox,
+	output::Output,
+	retry::{RetryPolicy, SendError},
+};
+
+/// Delivers events to the remote broker.
+#[derive(Debug, Clone)]
+struct Sender {
+	client: Client,
+	opt: Arc<Opt>,
+	policy: RetryPolicy,
+	auth: Arc<Auth>,
+	outbox: Option<Arc<Outbox>>,
+	output: Output,
+}
+
+impl Sender {
+	/// Send new events, persisting them to the outbox first if there is one.
+	async fn send(&self, events: Vec<AnyEvent>) {
+		let entry = match &self.outbox {
+			Some(outbox) => match outbox.push(&events).await {
+				Ok(path) => Some(path),
+				Err(err) => {
+					warn!(%err, "Unable to write event to outbox");
+					None
+				}
+			},
+			None => None,
+		};
+
+		self.deliver(events, entry).await;
+	}
+
+	/// Deliver events, removing their outbox entry once they no longer need to be sent. Entries of
+	/// events that fail with a retryable error are kept to be redelivered later.
+	///
+	/// In batch mode, the events are sent together in a single request. Otherwise, each is sent in
+	/// its own request, in order: after a retryable failure, the rest are kept in the entry without
+	/// being sent, and the entry is rewritten so that events already delivered aren't sent again.
+	async fn deliver(&self, events: Vec<AnyEvent>, entry: Option<PathBuf>) {
+		let undelivered = if self.opt.batch {
+			match self.deliver_batch(&events).await {
+				Ok(()) => 0,
+				Err(err) => {
+					warn!(%err, "Unable to deliver batch");
+					if err.is_retryable() {
+						events.len()
+					} else {
+						0
+					}
+				}
+			}
+		} else {
+			let mut undelivered = 0;
+			for (i, event) in events.iter().enumerate() {
+				if let Err(err) = self.deliver_event(event).await {
+					warn!(%err, "Unable to deliver event");
+					if err.is_retryable() {
+						undelivered = events.len() - i;
+						break;
+					}
+				}
+			}
+			undelivered
+		};
+
+		let (Some(outbox), Some(entry)) = (&self.outbox, entry) else {
+			return;
+		};
+
+		if undelivered == 0 {
+			outbox.complete(&entry).await;
+			return;
+		}
+
+		if undelivered < events.len() {
+			if let Err(err) = outbox
+				.replace(&entry, &events[events.len() - undelivered..])
+				.await
+			{
+				warn!(%err, ?entry, "Unable to update event in outbox");
+			}
+		}
+		outbox.release(&entry);
+	}
+
+	async fn deliver_event(&self, event: &AnyEvent) -> Result<(), SendError> {
+		let span = info_span!("deliver", event = %event.name);
+		trace::set_parent(&span, event.traceparent.as_deref());
+
+		async {
+			let data = to_vec(&event.data)?;
+			let url = format!("{}{}", self.opt.url, event.name);
+			let traceparent = trace::forward(event.traceparent.as_deref());
+
+			let body = self
+				.post(&url, &event.name, data, traceparent.as_deref())
+				.await?;
+			if self.opt.replies && !body.is_empty() {
+				self.write_reply(event, &body, traceparent.as_deref()).await;
+			}
+
+			Ok(())
+		}
+		.instrument(span)
+		.await
+	}
+
+	/// Deliver `events` to the batch endpoint, at the root of the URL. Responses to batches are
+	/// not replied with, and each event carries its own traceparent.
+	async fn deliver_batch(&self, events: &[AnyEvent]) -> Result<(), SendError> {
+		let data = to_vec(events)?;
+
+		self.post(self.opt.url.as_str(), "", data, None).await?;
+		Ok(())
+	}
+
+	/// Send `data` to `url` with the `traceparent` header, returning the body of the response.
+	async fn post(
+		&self,
+		url: &str,
+		name: &str,
+		data: Vec<u8>,
+		traceparent: Option<&str>,
+	) -> Result<Bytes, SendError> {
+		let response = self
+			.policy
+			.send(|| {
+				let mut request = self
+					.client
+					.request(self.opt.method.clone(), url)
+					.body(data.clone());
+				if let Some(traceparent) = traceparent {
+					request = request.header(trace::TRACEPARENT, traceparent);
+				}
+
+				self.auth.sign(request, name, &data)
+			})
+			.await?;
+
+		debug!(?response);
+		Ok(response.bytes().await?)
+	}
+
+	/// Write the response `body` to `event` to STDOUT as a reply event with the same name. Events
+	/// without an `id` can't be replied to, since the reply would look like a new event.
+	async fn write_reply(&self, event: &AnyEvent, body: &[u8], traceparent: Option<&str>) {
+		let Some(id) = &event.id else {
+			debug!(event = %event.name, "Not replying to event without an ID");
+			return;
+		};
+
+		let data = match from_slice::<Value>(body) {
+			Ok(data) => data,
+			Err(err) => {
+				warn!(%err, event = %event.name, "Unable to decode reply");
+				return;
+			}
+		};
+
+		let reply = Event {
+			reply_to: Some(id.clone()),
+			traceparent: traceparent.map(Into::into),
+			..Event::new(event.name.clone(), data)
+		};
+		debug!(?reply);
+
+		match self.output.write(&reply).await {
+			Ok(()) => metrics().event_out(&reply.name),
+			Err(err) => warn!(%err, "Unable to write reply"),
+		}
+	}
+}
+
+/// Orders deliveries of events that share a key, while events with different keys are delivered
+/// concurrently.
+#[derive(Debug, Default)]
+struct Lanes {
+	tails: HashMap<String, Shared<oneshot::Receiver<()>>>,
+}
+
+/// A delivery's place in its lanes.
+#[derive(Debug)]
+struct Lane {
+	/// Resolve once every earlier delivery in the lanes has completed.
+	prev: Vec<Shared<oneshot::Receiver<()>>>,
+	/// Signals that this delivery has completed.
+	done: oneshot::Sender<()>,
+}
+
+impl Lanes {
+	/// Enter the lanes for `keys`.
+	fn enter(&mut self, keys: impl IntoIterator<Item = String>) -> Lane {
+		// Forget lanes with nothing left to deliver.
+		if self.tails.len() >= MAX_IDLE_LANES {
+			self.tails.retain(|_, tail| tail.peek().is_none());
+		}
+
+		let (tx, rx) = oneshot::channel();
+		let rx = rx.shared();
+		let prev = keys
+			.into_iter()
+			.filter_map(|key| self.tails.insert(key, rx.clone()))
+			.collect();
+
+		Lane { prev, done: tx }
+	}
+}
+
+const MAX_IDLE_LANES: usize = 1024;
+
+/// The key of the lane `event` is delivered in, if any.
+fn order_key(order_by: OrderBy, event: &AnyEvent) -> Option<String> {
+	match order_by {
+		OrderBy::None => None,
+		OrderBy::Event => Some(event.name.clone()),
+		OrderBy::Guild => event
+			.data
+			.as_map()?
+			.iter()
+			.find(|(key, _)| key.as_str() == Some("guild_id"))
+			.map(|(_, id)| id.as_str().map_or_else(|| id.to_string(), str::to_string)),
+	}
+}
+
+/// Events waiting to be sent together.
+#[derive(Debug, Default)]
+struct Batch {
+	events: Vec<AnyEvent>,
+	size: usize,
+	deadline: Option<Instant>,
+}
+
+impl Batch {
+	fn push(&mut self, event: AnyEvent, opt: &Opt) {
+		self.size += to_vec(&event).map_or(0, |bytes| bytes.len());
+		self.events.push(event);
+		self.deadline
+			.get_or_insert_with(|| Instant::now() + opt.batch_max_delay);
+	}
+
+	fn is_full(&self, opt: &Opt) -> bool {
+		self.events.len() >= opt.batch_max_events || self.size >= opt.batch_max_bytes
+	}
+
+	fn take(&mut self) -> Vec<AnyEvent> {
+		self.size = 0;
+		self.deadline = None;
+		std::mem::take(&mut self.events)
+	}
+}
+
+/// Sends events read from STDIN and redelivers those left in the outbox, limiting how many are
+/// in flight at once.
+#[derive(Debug, Clone)]
+struct Dispatcher {
+	sender: Sender,
+	semaphore: Arc<Semaphore>,
+	lanes: Arc<Mutex<Lanes>>,
+}
+
+impl Dispatcher {
+	/// Wait for capacity, then send `events` in the background. Until then, they're counted as
+	/// pending.
+	async fn dispatch(&self, events: Vec<AnyEvent>) -> anyhow::Result<()> {
+		if events.is_empty() {
+			return Ok(());
+		}
+
+		let pending = metrics().pending.with_label_values(&["outgoing"]);
+		pending.inc();
+		let permit = self.semaphore.clone().acquire_owned().await;
+		pending.dec();
+
+		self.spawn(events, None, permit?);
+		Ok(())
+	}
+
+	/// Send `events` in the background with the capacity held by `permit`. Events that already
+	/// have an outbox `entry` are redelivered from it.
+	fn spawn(&self, events: Vec<AnyEvent>, entry: Option<PathBuf>, permit: OwnedSemaphorePermit) {
+		let order_by = self.sender.opt.order_by;
+		let keys = events
+			.iter()
+			.filter_map(|event| order_key(order_by, event))
+			.collect::<Vec<_>>();
+		let lane = (!keys.is_empty()).then(|| self.lanes.lock().unwrap().enter(keys));
+
+		let span = match &entry {
+			Some(entry) => info_span!("redeliver", ?events, ?entry),
+			None => info_span!("make_request", ?events),
+		};
+		tokio::spawn(
+			send_in_lane(self.sender.clone(), events, entry, lane, permit).instrument(span),
+		);
+	}
+
+	/// Periodically redeliver events left in the outbox, including any left from a previous run.
+	/// Entries are only read once there is capacity to send them, a page of as many entries as
+	/// there is capacity for at a time.
+	async fn redeliver(self, outbox: Arc<Outbox>, period: Duration) {
+		let mut interval = interval(period);
+
+		loop {
+			interval.tick().await;
+
+			// Entries that fail again during this round are left for the next one.
+			let mut last = None;
+			loop {
+				let Ok(permit) = self.semaphore.clone().acquire_owned().await else {
+					return;
+				};
+				let mut permits = vec![permit];
+				while let Ok(permit) = self.semaphore.clone().try_acquire_owned() {
+					permits.push(permit);
+				}
+
+				let page = permits.len();
+				let pending = match outbox.claim_pending(last.as_deref(), page).await {
+					Ok(pending) => pending,
+					Err(err) => {
+						warn!(%err, "Unable to read outbox");
+						break;
+					}
+				};
+
+				let done = pending.len() < page;
+				for ((entry, events), permit) in pending.into_iter().zip(permits) {
+					last = Some(entry.clone());
+					self.spawn(events, Some(entry), permit);
+				}
+
+				if done {
+					break;
+				}
+			}
+		}
+	}
+}
+
+async fn send_in_lane(
+	sender: Sender,
+	events: Vec<AnyEvent>,
+	entry: Option<PathBuf>,
+	lane: Option<Lane>,
+	permit: OwnedSemaphorePermit,
+) {
+	let done = match lane {
+		Some(lane) => {
+			join_all(lane.prev).await;
+			Some(lane.done)
+		}
+		None => None,
+	};
+
+	match entry {
+		Some(entry) => sender.deliver(events, Some(entry)).await,
+		None => sender.send(events).await,
+	}
+
+	if let Some(done) = done {
+		let _ = done.send(());
+	}
+	drop(permit);
+}
+
+pub async fn handle_http_out(
+	opt: Opt,
+	output: Output,
+	mut events: impl Stream<Item = AnyEvent> + Unpin,
+) -> anyhow::Result<()> {
+	let outbox = match &opt.outbox {
+		Some(dir) => Some(Arc::new(Outbox::open(dir).await?)),
+		None => None,
+	};
+
+	let max_in_flight = opt.max_in_flight;
+	let semaphore = Arc::new(Semaphore::new(max_in_flight));
+	metrics()
+		.capacity
+		.with_label_values(&["outgoing"])
+		.set(max_in_flight as i64);
+
+	let sender = Sender {
+		client: Client::new(),
+		policy: RetryPolicy::from(&opt),
+		auth: Arc::new(Auth::from(&opt)),
+		outbox: outbox.clone(),
+		output,
+		opt: Arc::new(opt),
+	};
+
+	let opt = sender.opt.clone();
+	let dispatcher = Dispatcher {
+		sender,
+		semaphore: semaphore.clone(),
+		lanes: Arc::default(),
+	};
+
+	let redeliver = outbox.map(|outbox| {
+		let period = opt.outbox_interval;
+		tokio::spawn(dispatcher.clone().redeliver(outbox, period))
+	});
+
+	let mut batch = Batch::default();
+
+	loop {
+		// Only wait for more events until the pending batch is due. Capacity is acquired before
+		// reading the next event, so that a burst of events is held back in STDIN instead of
+		// being buffered in memory.
+		let next = match batch.deadline {
+			Some(deadline) => timeout_at(deadline, events.next()).await.ok(),
+			None => Some(events.next().await),
+		};
+
+		match next {
+			Some(Some(event)) if opt.batch => {
+				batch.push(event, &opt);
+				if batch.is_full(&opt) {
+					dispatcher.dispatch(batch.take()).await?;
+				}
+			}
+			Some(Some(event)) => dispatcher.dispatch(vec![event]).await?,
+			Some(None) => {
+				dispatcher.dispatch(batch.take()).await?;
+				break;
+			}
+			None => dispatcher.dispatch(batch.take()).await?,
+		}
+	}
+
+	// Stop redelivering, then wait for every in-flight request to complete.
+	if let Some(redeliver) = redeliver {
+		redeliver.abort();
+	}
+	let _ = semaphore.acquire_many(max_in_flight as u32).await?;
+
+	Ok(())
+}
@@ -0,0 +1,260 @@
+use std::{
+	collections::HashSet,
+	io,
+	path::{Path, PathBuf},
+	sync::{
+		atomic::{AtomicU64, Ordering},
+		Mutex,
+	},
+	time::{SystemTime, UNIX_EPOCH},
+};
+
+use spectacles::{from_slice, to_vec, AnyEvent};
+use tokio::fs;
+use tracing::warn;
+
+const EVENT_EXTENSION: &str = "event";
+const TEMP_EXTENSION: &str = "tmp";
+
+/// Events persisted on disk until they are delivered, so they survive restarts of the broker.
+///
+/// Each delivery, either a single event or a batch, is stored in its own file, named so that files
+/// sort in the order their events were received.
+#[derive(Debug)]
+pub struct Outbox {
+	dir: PathBuf,
+	seq: AtomicU64,
+	in_flight: Mutex<HashSet<PathBuf>>,
+}
+
+impl Outbox {
+	pub async fn open(dir: impl Into<PathBuf>) -> io::Result<Self> {
+		let dir = dir.into();
+		fs::create_dir_all(&dir).await?;
+
+		Ok(Self {
+			dir,
+			seq: AtomicU64::new(0),
+			in_flight: Mutex::default(),
+		})
+	}
+
+	/// Persist `events`, returning the path of their entry. The entry is in flight until it is
+	/// [completed](Self::complete) or [released](Self::release).
+	pub async fn push(&self, events: &[AnyEvent]) -> io::Result<PathBuf> {
+		let nanos = SystemTime::now()
+			.duration_since(UNIX_EPOCH)
+			.unwrap_or_default()
+			.as_nanos();
+		let seq = self.seq.fetch_add(1, Ordering::Relaxed);
+
+		let path = self
+			.dir
+			.join(format!("{:020}-{:010}", nanos, seq))
+			.with_extension(EVENT_EXTENSION);
+
+		// Mark the entry in flight before it appears, so that it can't be claimed while it's
+		// being delivered.
+		self.in_flight.lock().unwrap().insert(path.clone());
+
+		if let Err(err) = write_events(&path, events).await {
+			self.release(&path);
+			return Err(err);
+		}
+
+		Ok(path)
+	}
+
+	/// Replace the events of an entry that is in flight, such as with those left to deliver.
+	pub async fn replace(&self, path: &Path, events: &[AnyEvent]) -> io::Result<()> {
+		write_events(path, events).await
+	}
+
+	/// Take up to `limit` persisted entries that aren't already in flight, oldest first, marking
+	/// them as in flight. Only the entries taken are read, so a large outbox can be claimed a page
+	/// at a time, each page starting `after` the last entry of the previous one. Entries that can't
+	/// be decoded are skipped.
+	pub async fn claim_pending(
+		&self,
+		after: Option<&Path>,
+		limit: usize,
+	) -> io::Result<Vec<(PathBuf, Vec<AnyEvent>)>> {
+		let mut paths = Vec::new();
+		let mut entries = fs::read_dir(&self.dir).await?;
+		while let Some(entry) = entries.next_entry().await? {
+			let path = entry.path();
+			let is_event = path.extension().and_then(|ext| ext.to_str()) == Some(EVENT_EXTENSION);
+			if is_event && after.is_none_or(|after| path.as_path() > after) {
+				paths.push(path);
+			}
+		}
+		paths.sort();
+
+		let mut pending = Vec::new();
+		for path in paths {
+			if pending.len() >= limit {
+				break;
+			}
+
+			if !self.in_flight.lock().unwrap().insert(path.clone()) {
+				continue;
+			}
+
+			match read_events(&path).await {
+				Ok(events) => pending.push((path, events)),
+				Err(err) => {
+					warn!(%err, ?path, "Unable to read event from outbox");
+					self.release(&path);
+				}
+			}
+		}
+
+		Ok(pending)
+	}
+
+	/// Remove a delivered entry.
+	pub async fn complete(&self, path: &Path) {
+		if let Err(err) = fs::remove_file(path).await {
+			warn!(%err, ?path, "Unable to remove event from outbox");
+		}
+
+		self.release(path);
+	}
+
+	/// Keep an undelivered entry, allowing it to be claimed again later.
+	pub fn release(&self, path: &Path) {
+		self.in_flight.lock().unwrap().remove(path);
+	}
+}
+
+/// Write `events` to `path`, through a temporary file so that a crash can't leave a partial entry
+/// behind.
+async fn write_events(path: &Path, events: &[AnyEvent]) -> io::Result<()> {
+	let bytes = to_vec(events).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+	let temp = path.with_extension(TEMP_EXTENSION);
+
+	fs::write(&temp, bytes).await?;
+	fs::rename(&temp, path).await
+}
+
+async fn read_events(path: &Path) -> io::Result<Vec<AnyEvent>> {
+	let bytes = fs::read(path).await?;
+	from_slice(&bytes).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+}
+
+#[cfg(test)]
+mod tests {
+	use std::env::temp_dir;
+
+	use rand::random;
+	use spectacles::Value;
+
+	use super::*;
+
+	fn event(name: &str) -> AnyEvent {
+		AnyEvent::new(name.to_string(), Value::Nil)
+	}
+
+	fn names(events: &[AnyEvent]) -> Vec<&str> {
+		events.iter().map(|event| event.name.as_str()).collect()
+	}
+
+	async fn outbox() -> Outbox {
+		let dir = temp_dir().join(format!("spectacles-outbox-{:016x}", random::<u64>()));
+		Outbox::open(dir).await.unwrap()
+	}
+
+	#[tokio::test]
+	async fn entries_in_flight_are_not_claimed() {
+		let outbox = outbox().await;
+		let a = outbox.push(&[event("A")]).await.unwrap();
+		let b = outbox.push(&[event("B")]).await.unwrap();
+		assert!(outbox
+			.claim_pending(None, usize::MAX)
+			.await
+			.unwrap()
+			.is_empty());
+
+		outbox.release(&a);
+		outbox.release(&b);
+		let pending = outbox.claim_pending(None, usize::MAX).await.unwrap();
+		assert_eq!(
+			pending
+				.iter()
+				.map(|(path, events)| (path, names(events)))
+				.collect::<Vec<_>>(),
+			[(&a, vec!["A"]), (&b, vec!["B"])]
+		);
+		assert!(outbox
+			.claim_pending(None, usize::MAX)
+			.await
+			.unwrap()
+			.is_empty());
+
+		outbox.complete(&a).await;
+		outbox.release(&b);
+		let pending = outbox.claim_pending(None, usize::MAX).await.unwrap();
+		assert_eq!(pending.len(), 1);
+		assert_eq!(pending[0].0, b);
+
+		fs::remove_dir_all(&outbox.dir).await.unwrap();
+	}
+
+	#[tokio::test]
+	async fn entries_are_claimed_in_pages() {
+		let outbox = outbox().await;
+		let mut paths = Vec::new();
+		for name in ["A", "B", "C"] {
+			let path = outbox.push(&[event(name)]).await.unwrap();
+			outbox.release(&path);
+			paths.push(path);
+		}
+
+		let claimed = |pending: Vec<(PathBuf, Vec<AnyEvent>)>| {
+			pending
+				.into_iter()
+				.flat_map(|(_, events)| events)
+				.map(|event| event.name)
+				.collect::<Vec<_>>()
+		};
+		assert_eq!(
+			claimed(outbox.claim_pending(None, 2).await.unwrap()),
+			["A", "B"]
+		);
+		assert_eq!(claimed(outbox.claim_pending(None, 2).await.unwrap()), ["C"]);
+		assert!(outbox.claim_pending(None, 0).await.unwrap().is_empty());
+
+		// Entries released since are only claimed again by a page starting before them.
+		outbox.release(&paths[0]);
+		outbox.release(&paths[2]);
+		assert!(outbox
+			.claim_pending(Some(&paths[2]), 2)
+			.await
+			.unwrap()
+			.is_empty());
+		assert_eq!(
+			claimed(outbox.claim_pending(Some(&paths[0]), 2).await.unwrap()),
+			["C"]
+		);
+
+		fs::remove_dir_all(&outbox.dir).await.unwrap();
+	}
+
+	#[tokio::test]
+	async fn replace_keeps_undelivered_events() {
+		let outbox = outbox().await;
+		let path = outbox
+			.push(&[event("A"), event("B"), event("C")])
+			.await
+			.unwrap();
+
+		outbox.replace(&path, &[event("C")]).await.unwrap();
+		outbox.release(&path);
+
+		let pending = outbox.claim_pending(None, usize::MAX).await.unwrap();
+		assert_eq!(pending.len(), 1);
+		assert_eq!(names(&pending[0].1), ["C"]);
+
+		fs::remove_dir_all(&outbox.dir).await.unwrap();
+	}
+}
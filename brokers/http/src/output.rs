@@ -0,0 +1,196 @@
+use std::io;
+
+use reqwest::StatusCode;
+use serde::Serialize;
+use spectacles::{admin, to_vec, EncodeError};
+use thiserror::Error;
+use tokio::{
+	io::{stdout, AsyncWrite, AsyncWriteExt, BufWriter},
+	sync::{mpsc, oneshot},
+};
+use tracing::error;
+
+/// The number of frames that may wait to be written before writers have to wait.
+const OUTPUT_BUFFER: usize = 256;
+
+#[derive(Debug, Error)]
+pub enum WriteError {
+	#[error("unable to encode event: {0}")]
+	Encode(#[from] EncodeError),
+	#[error("unable to write to STDOUT: {0}")]
+	Io(#[from] io::Error),
+	#[error("STDOUT is closed")]
+	Closed,
+}
+
+impl WriteError {
+	/// The status to respond to a request with when its event couldn't be written.
+	pub fn status(&self) -> StatusCode {
+		match self {
+			Self::Encode(_) => StatusCode::INTERNAL_SERVER_ERROR,
+			Self::Io(_) | Self::Closed => StatusCode::SERVICE_UNAVAILABLE,
+		}
+	}
+}
+
+#[derive(Debug)]
+struct Frame {
+	bytes: Vec<u8>,
+	written: oneshot::Sender<io::Result<()>>,
+}
+
+/// Writes to STDOUT from a single task, so that frames from concurrent writers are never
+/// interleaved.
+///
+/// Unlike [`spectacles::io::write`], each write is acknowledged once flushed, so that requests are
+/// only accepted once their events are written, and batches are written as one frame. Frames are
+/// encoded with [`to_vec`], the same encoding as the writer's, so they're read the same way.
+#[derive(Debug, Clone)]
+pub struct Output {
+	tx: mpsc::Sender<Frame>,
+}
+
+impl Output {
+	pub fn spawn() -> Self {
+		let (tx, rx) = mpsc::channel(OUTPUT_BUFFER);
+		tokio::spawn(write_frames(stdout(), rx));
+
+		Self { tx }
+	}
+
+	/// Write `value` to STDOUT, waiting until it has been flushed.
+	pub async fn write(&self, value: &impl Serialize) -> Result<(), WriteError> {
+		self.send(to_vec(value)?).await
+	}
+
+	/// Write `values` to STDOUT one after another, without any other writes between them.
+	pub async fn write_all<T: Serialize>(&self, values: &[T]) -> Result<(), WriteError> {
+		let mut bytes = Vec::new();
+		for value in values {
+			bytes.extend(to_vec(value)?);
+		}
+
+		self.send(bytes).await
+	}
+
+	async fn send(&self, bytes: Vec<u8>) -> Result<(), WriteError> {
+		let (written, rx) = oneshot::channel();
+		let frame = Frame { bytes, written };
+
+		self.tx.send(frame).await.map_err(|_| WriteError::Closed)?;
+		Ok(rx.await.map_err(|_| WriteError::Closed)??)
+	}
+}
+
+/// Write frames as they arrive, flushing whenever no more are waiting. Stops at the first error,
+/// since a frame may have been partly written, and anything written after it would be decoded
+/// from the middle of it.
+async fn write_frames(out: impl AsyncWrite + Unpin, mut rx: mpsc::Receiver<Frame>) {
+	let mut out = BufWriter::new(out);
+	let status = admin::component("stdout");
+	status.up();
+
+	while let Some(frame) = rx.recv().await {
+		let mut frames = vec![frame];
+		while let Ok(frame) = rx.try_recv() {
+			frames.push(frame);
+		}
+
+		let result = async {
+			for frame in &frames {
+				out.write_all(&frame.bytes).await?;
+			}
+
+			out.flush().await
+		}
+		.await;
+
+		for frame in frames {
+			let result = match &result {
+				Ok(()) => Ok(()),
+				Err(err) => Err(io::Error::new(err.kind(), err.to_string())),
+			};
+
+			let _ = frame.written.send(result);
+		}
+
+		if let Err(err) = result {
+			error!(%err, "Unable to write to STDOUT, no longer writing events");
+			status.down();
+			return;
+		}
+
+		status.up();
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::{
+		pin::Pin,
+		sync::{Arc, Mutex},
+		task::{Context, Poll},
+	};
+
+	use super::*;
+
+	/// Accepts up to `limit` bytes, then fails.
+	#[derive(Debug, Clone)]
+	struct Limited {
+		written: Arc<Mutex<Vec<u8>>>,
+		limit: usize,
+	}
+
+	impl AsyncWrite for Limited {
+		fn poll_write(
+			self: Pin<&mut Self>,
+			_: &mut Context<'_>,
+			buf: &[u8],
+		) -> Poll<io::Result<usize>> {
+			let mut written = self.written.lock().unwrap();
+			let len = buf.len().min(self.limit - written.len());
+			if len == 0 {
+				return Poll::Ready(Err(io::ErrorKind::BrokenPipe.into()));
+			}
+
+			written.extend(&buf[..len]);
+			Poll::Ready(Ok(len))
+		}
+
+		fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+			Poll::Ready(Ok(()))
+		}
+
+		fn poll_shutdown(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+			Poll::Ready(Ok(()))
+		}
+	}
+
+	async fn write(output: &Output, bytes: &[u8]) -> Result<(), WriteError> {
+		output.send(bytes.to_vec()).await
+	}
+
+	#[tokio::test]
+	async fn writes_stop_after_a_partial_frame() {
+		let out = Limited {
+			written: Arc::default(),
+			limit: 6,
+		};
+		let (tx, rx) = mpsc::channel(OUTPUT_BUFFER);
+		let writer = tokio::spawn(write_frames(out.clone(), rx));
+		let output = Output { tx };
+
+		assert!(write(&output, b"abcd").await.is_ok());
+		assert!(matches!(
+			write(&output, b"efgh").await,
+			Err(WriteError::Io(_))
+		));
+		assert!(matches!(
+			write(&output, b"ijkl").await,
+			Err(WriteError::Closed)
+		));
+
+		writer.await.unwrap();
+		assert_eq!(*out.written.lock().unwrap(), b"abcdef");
+	}
+}
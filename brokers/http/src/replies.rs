@@ -0,0 +1,80 @@
+use std::{
+	collections::HashMap,
+	sync::{Arc, Mutex},
+	time::Duration,
+};
+
+use spectacles::AnyEvent;
+use tokio::{sync::oneshot, time::timeout};
+
+/// Incoming requests held open until a reply to their event is read from STDIN.
+#[derive(Debug)]
+pub struct Replies {
+	timeout: Duration,
+	pending: Mutex<HashMap<String, oneshot::Sender<AnyEvent>>>,
+}
+
+impl Replies {
+	pub fn new(timeout: Duration) -> Self {
+		Self {
+			timeout,
+			pending: Mutex::default(),
+		}
+	}
+
+	/// Generate an event ID and start waiting for a reply to it.
+	pub fn wait(self: &Arc<Self>) -> PendingReply {
+		let id = format!("{:032x}", rand::random::<u128>());
+		let (tx, rx) = oneshot::channel();
+		self.pending.lock().unwrap().insert(id.clone(), tx);
+
+		PendingReply {
+			replies: self.clone(),
+			id,
+			rx,
+		}
+	}
+
+	/// Hand `event` to the request it replies to. Returns the event back if no request is waiting
+	/// for it.
+	pub fn resolve(&self, event: AnyEvent) -> Option<AnyEvent> {
+		let tx = event
+			.reply_to
+			.as_ref()
+			.and_then(|id| self.pending.lock().unwrap().remove(id));
+
+		match tx {
+			Some(tx) => {
+				let _ = tx.send(event);
+				None
+			}
+			None => Some(event),
+		}
+	}
+}
+
+/// A request waiting for a reply. The request stops waiting when this is dropped.
+#[derive(Debug)]
+pub struct PendingReply {
+	replies: Arc<Replies>,
+	pub id: String,
+	rx: oneshot::Receiver<AnyEvent>,
+}
+
+impl PendingReply {
+	/// Wait for the reply, returning `None` if it doesn't arrive in time.
+	pub async fn recv(&mut self) -> Option<AnyEvent> {
+		self.recv_within(self.replies.timeout).await
+	}
+
+	/// Wait up to `duration` for the reply.
+	pub async fn recv_within(&mut self, duration: Duration) -> Option<AnyEvent> {
+		timeout(duration, &mut self.rx).await.ok()?.ok()
+	}
+}
+
+impl Drop for PendingReply {
+	fn drop(&mut self) {
+		self.replies.pending.lock().unwrap().remove(&self.id);
+	}
+}
@@ -0,0 +1,192 @@
+use std::time::{Duration, SystemTime};
+
+use httpdate::parse_http_date;
+use rand::{thread_rng, Rng};
+use reqwest::{
+	header::{HeaderMap, RETRY_AFTER},
+	RequestBuilder, Response, StatusCode,
+};
+use spectacles::EncodeError;
+use thiserror::Error;
+use tokio::time::sleep;
+use tracing::warn;
+
+use crate::options::Opt;
+
+#[derive(Debug, Error)]
+pub enum SendError {
+	#[error("unable to encode event: {0}")]
+	Encode(#[from] EncodeError),
+	#[error("request failed: {0}")]
+	Request(#[from] reqwest::Error),
+	#[error("server responded with {0}")]
+	Status(StatusCode),
+}
+
+impl SendError {
+	/// Whether the request may succeed if it is made again.
+	pub fn is_retryable(&self) -> bool {
+		match self {
+			Self::Encode(_) => false,
+			Self::Request(err) => !err.is_builder(),
+			Self::Status(status) => {
+				status.is_server_error() || *status == StatusCode::TOO_MANY_REQUESTS
+			}
+		}
+	}
+}
+
+/// How failed requests are retried.
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+	pub max_attempts: u32,
+	pub initial_backoff: Duration,
+	pub max_backoff: Duration,
+}
+
+impl From<&Opt> for RetryPolicy {
+	fn from(opt: &Opt) -> Self {
+		Self {
+			max_attempts: opt.max_attempts.max(1),
+			initial_backoff: opt.initial_backoff,
+			max_backoff: opt.max_backoff,
+		}
+	}
+}
+
+impl RetryPolicy {
+	/// Send the request built by `make_request`, retrying on connection errors, 5xx and 429
+	/// responses. Responses with a `Retry-After` header are retried after the requested delay;
+	/// otherwise, retries back off exponentially with full jitter.
+	pub async fn send<F>(&self, make_request: F) -> Result<Response, SendError>
+	where
+		F: Fn() -> RequestBuilder,
+	{
+		let mut attempt = 0;
+
+		loop {
+			attempt += 1;
+
+			let (err, retry_after) = match make_request().send().await {
+				Ok(response) if response.status().is_success() => return Ok(response),
+				Ok(response) => (
+					SendError::Status(response.status()),
+					retry_after(response.headers()),
+				),
+				Err(err) => (SendError::Request(err), None),
+			};
+
+			if !err.is_retryable() || attempt >= self.max_attempts {
+				return Err(err);
+			}
+
+			let delay = self.delay(attempt, retry_after);
+			warn!(%err, attempt, ?delay, "Retrying request");
+			sleep(delay).await;
+		}
+	}
+
+	/// The delay before retrying after `attempt`: the server's `Retry-After`, if any, up to the
+	/// maximum backoff, or else the [backoff](Self::backoff).
+	fn delay(&self, attempt: u32, retry_after: Option<Duration>) -> Duration {
+		match retry_after {
+			Some(retry_after) => retry_after.min(self.max_backoff),
+			None => self.backoff(attempt),
+		}
+	}
+
+	/// A random delay between 0 and the exponential backoff for `attempt`.
+	pub fn backoff(&self, attempt: u32) -> Duration {
+		let ceiling = self
+			.initial_backoff
+			.saturating_mul(2u32.saturating_pow(attempt - 1))
+			.min(self.max_backoff);
+
+		ceiling.mul_f64(thread_rng().gen())
+	}
+}
+
+/// Parse a `Retry-After` header, given either in seconds or as an HTTP date.
+fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+	let value = headers.get(RETRY_AFTER)?.to_str().ok()?;
+
+	match value.parse() {
+		Ok(secs) => Some(Duration::from_secs(secs)),
+		Err(_) => parse_http_date(value)
+			.ok()?
+			.duration_since(SystemTime::now())
+			.ok(),
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use httpdate::fmt_http_date;
+	use reqwest::header::HeaderValue;
+
+	use super::*;
+
+	const POLICY: RetryPolicy = RetryPolicy {
+		max_attempts: 5,
+		initial_backoff: Duration::from_millis(100),
+		max_backoff: Duration::from_secs(1),
+	};
+
+	fn headers(value: &str) -> HeaderMap {
+		let mut headers = HeaderMap::new();
+		headers.insert(RETRY_AFTER, HeaderValue::from_str(value).unwrap());
+		headers
+	}
+
+	#[test]
+	fn backoff_grows_exponentially() {
+		for (attempt, ceiling) in [(1, 100), (2, 200), (3, 400), (4, 800)] {
+			for _ in 0..100 {
+				assert!(POLICY.backoff(attempt) <= Duration::from_millis(ceiling));
+			}
+		}
+	}
+
+	#[test]
+	fn backoff_is_capped() {
+		for attempt in [5, 10, 32, u32::MAX] {
+			assert!(POLICY.backoff(attempt) <= POLICY.max_backoff);
+		}
+	}
+
+	#[test]
+	fn retry_after_seconds() {
+		assert_eq!(retry_after(&headers("120")), Some(Duration::from_secs(120)));
+		assert_eq!(retry_after(&headers("0")), Some(Duration::ZERO));
+	}
+
+	#[test]
+	fn retry_after_date() {
+		let date = fmt_http_date(SystemTime::now() + Duration::from_secs(60));
+		let delay = retry_after(&headers(&date)).unwrap();
+		assert!(delay > Duration::from_secs(55) && delay <= Duration::from_secs(60));
+
+		let past = fmt_http_date(SystemTime::now() - Duration::from_secs(60));
+		assert_eq!(retry_after(&headers(&past)), None);
+	}
+
+	#[test]
+	fn retry_after_invalid() {
+		assert_eq!(retry_after(&HeaderMap::new()), None);
+		assert_eq!(retry_after(&headers("soon")), None);
+		assert_eq!(retry_after(&headers("-1")), None);
+	}
+
+	#[test]
+	fn retry_after_is_capped() {
+		assert_eq!(
+			POLICY.delay(1, Some(Duration::from_millis(300))),
+			Duration::from_millis(300)
+		);
+		assert_eq!(
+			POLICY.delay(1, Some(Duration::from_secs(3600))),
+			POLICY.max_backoff
+		);
+		assert!(POLICY.delay(1, None) <= POLICY.initial_backoff);
+	}
+}
@@ -0,0 +1,166 @@
+use std::{collections::HashSet, convert::Infallible, future::ready, sync::Arc};
+
+use axum::{
+	extract::Query,
+	http::{header::AUTHORIZATION, HeaderMap, HeaderValue},
+	response::{
+		sse::{Event, KeepAlive, Sse},
+		IntoResponse, Response,
+	},
+	Extension,
+};
+use futures::{stream, StreamExt};
+use reqwest::StatusCode;
+use serde::Deserialize;
+use spectacles::AnyEvent;
+use tracing::warn;
+
+use crate::{
+	auth::{Auth, SIGNATURE_HEADER, TIMESTAMP_HEADER},
+	hub::Hub,
+};
+
+#[derive(Debug, Default, Deserialize)]
+pub struct Filter {
+	/// A comma-separated list of event names to receive. Every event is received if absent.
+	events: Option<String>,
+	/// The bearer token, for clients like `EventSource` that can't set headers.
+	token: Option<String>,
+	/// The timestamp of a signed URL, signed for an empty event name and body.
+	timestamp: Option<String>,
+	/// The signature of a signed URL.
+	signature: Option<String>,
+}
+
+impl Filter {
+	/// The credentials of a request, from its headers or else from its query parameters.
+	fn credentials(&self, headers: &HeaderMap) -> HeaderMap {
+		let mut credentials = headers.clone();
+		let params = [
+			(
+				AUTHORIZATION.as_str(),
+				self.token.as_ref().map(|token| format!("Bearer {}", token)),
+			),
+			(TIMESTAMP_HEADER, self.timestamp.clone()),
+			(SIGNATURE_HEADER, self.signature.clone()),
+		];
+
+		for (name, value) in params {
+			let value = value.and_then(|value| HeaderValue::try_from(value).ok());
+			if let (false, Some(value)) = (credentials.contains_key(name), value) {
+				credentials.insert(name, value);
+			}
+		}
+
+		credentials
+	}
+}
+
+/// Stream events from STDIN to a subscriber as Server-Sent Events, each with the JSON-encoded
+/// event as its data.
+pub async fn handle_sse(
+	Query(filter): Query<Filter>,
+	Extension(auth): Extension<Arc<Auth>>,
+	Extension(hub): Extension<Hub>,
+	headers: HeaderMap,
+) -> Response {
+	if let Err(err) = auth.verify(&filter.credentials(&headers), "", &[]) {
+		warn!(%err, "Rejected unauthenticated SSE subscriber");
+		return StatusCode::UNAUTHORIZED.into_response();
+	}
+
+	let names = filter
+		.events
+		.map(|events| events.split(',').map(str::to_owned).collect::<HashSet<_>>());
+
+	let events = stream::unfold(hub.subscribe(), |mut subscriber| async move {
+		let event = subscriber.recv().await?;
+		Some((event, subscriber))
+	})
+	.filter_map(move |event| {
+		let sse = if names
+			.as_ref()
+			.is_none_or(|names| names.contains(&event.name))
+		{
+			encode(&event)
+		} else {
+			None
+		};
+
+		ready(sse.map(Ok::<_, Infallible>))
+	});
+
+	Sse::new(events)
+		.keep_alive(KeepAlive::default())
+		.into_response()
+}
+
+fn encode(event: &AnyEvent) -> Option<Event> {
+	match Event::default().json_data(event) {
+		Ok(sse) => Some(sse),
+		Err(err) => {
+			warn!(%err, event = %event.name, "Unable to encode event as JSON");
+			None
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::time::Duration;
+
+	use super::*;
+
+	fn auth() -> Auth {
+		Auth::new(
+			Some("token".parse().unwrap()),
+			Some("secret".parse().unwrap()),
+			Duration::from_secs(300),
+		)
+	}
+
+	#[test]
+	fn credentials_from_query() {
+		let auth = auth();
+		let signed = auth.headers("", &[]);
+		let param = |name: &str| Some(signed[name].to_str().unwrap().to_string());
+
+		let filter = Filter {
+			token: Some("token".to_string()),
+			timestamp: param(TIMESTAMP_HEADER),
+			signature: param(SIGNATURE_HEADER),
+			..Filter::default()
+		};
+		assert!(auth
+			.verify(&filter.credentials(&HeaderMap::new()), "", &[])
+			.is_ok());
+
+		let filter = Filter {
+			token: Some("wrong".to_string()),
+			..filter
+		};
+		assert!(auth
+			.verify(&filter.credentials(&HeaderMap::new()), "", &[])
+			.is_err());
+	}
+
+	#[test]
+	fn headers_take_precedence() {
+		let auth = auth();
+		let filter = Filter {
+			token: Some("wrong".to_string()),
+			..Filter::default()
+		};
+
+		let headers = auth.headers("", &[]);
+		assert!(auth.verify(&filter.credentials(&headers), "", &[]).is_ok());
+	}
+
+	#[test]
+	fn no_credentials() {
+		let filter = Filter::default();
+		assert!(auth()
+			.verify(&filter.credentials(&HeaderMap::new()), "", &[])
+			.is_err());
+	}
+}
@@ -0,0 +1,110 @@
+use std::{io::BufReader, path::Path, sync::Arc, time::SystemTime};
+
+use anyhow::{anyhow, Context, Result};
+use axum_server::tls_rustls::RustlsConfig;
+use rustls::{
+	server::AllowAnyAuthenticatedClient, Certificate, PrivateKey, RootCertStore, ServerConfig,
+};
+use rustls_pemfile::Item;
+use tokio::{fs, time::interval};
+use tracing::{info, warn};
+
+use crate::options::Opt;
+
+/// Load the server TLS configuration from the certificate files in `opt`.
+pub async fn load_config(opt: &Opt) -> Result<ServerConfig> {
+	let cert_path = opt
+		.tls_cert
+		.as_ref()
+		.ok_or_else(|| anyhow!("--tls-cert is required to serve HTTPS"))?;
+	let key_path = opt
+		.tls_key
+		.as_ref()
+		.ok_or_else(|| anyhow!("--tls-key is required to serve HTTPS"))?;
+
+	let certs = load_certs(cert_path).await?;
+	let key = load_key(key_path).await?;
+
+	let builder = ServerConfig::builder().with_safe_defaults();
+	let builder = match &opt.tls_client_ca {
+		Some(ca_path) => {
+			let mut roots = RootCertStore::empty();
+			for cert in load_certs(ca_path).await? {
+				roots.add(&cert)?;
+			}
+
+			builder.with_client_cert_verifier(AllowAnyAuthenticatedClient::new(roots))
+		}
+		None => builder.with_no_client_auth(),
+	};
+
+	let mut config = builder.with_single_cert(certs, key)?;
+	config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+
+	Ok(config)
+}
+
+/// Periodically reload `config` whenever any of the certificate files in `opt` change.
+pub async fn reload_on_change(config: RustlsConfig, opt: Opt) {
+	let mut last_modified = modified(&opt).await;
+	let mut interval = interval(opt.tls_reload_interval);
+	interval.tick().await;
+
+	loop {
+		interval.tick().await;
+
+		let modified = modified(&opt).await;
+		if modified == last_modified {
+			continue;
+		}
+
+		match load_config(&opt).await {
+			Ok(new_config) => {
+				config.reload_from_config(Arc::new(new_config));
+				last_modified = modified;
+				info!("Reloaded TLS certificates");
+			}
+			Err(err) => warn!(%err, "Unable to reload TLS certificates"),
+		}
+	}
+}
+
+async fn modified(opt: &Opt) -> Vec<Option<SystemTime>> {
+	let mut times = Vec::new();
+	for path in [&opt.tls_cert, &opt.tls_key, &opt.tls_client_ca]
+		.into_iter()
+		.flatten()
+	{
+		let time = fs::metadata(path).await.and_then(|meta| meta.modified());
+		times.push(time.ok());
+	}
+
+	times
+}
+
+async fn load_certs(path: &Path) -> Result<Vec<Certificate>> {
+	let pem = fs::read(path)
+		.await
+		.with_context(|| format!("unable to read {}", path.display()))?;
+	let certs = rustls_pemfile::certs(&mut BufReader::new(pem.as_slice()))?;
+
+	if certs.is_empty() {
+		return Err(anyhow!("no certificates found in {}", path.display()));
+	}
+
+	Ok(certs.into_iter().map(Certificate).collect())
+}
+
+async fn load_key(path: &Path) -> Result<PrivateKey> {
+	let pem = fs::read(path)
+		.await
+		.with_context(|| format!("unable to read {}", path.display()))?;
+
+	rustls_pemfile::read_all(&mut BufReader::new(pem.as_slice()))?
+		.into_iter()
+		.find_map(|item| match item {
+			Item::PKCS8Key(key) | Item::RSAKey(key) | Item::ECKey(key) => Some(PrivateKey(key)),
+			_ => None,
+		})
+		.ok_or_else(|| anyhow!("no private key found in {}", path.display()))
+}
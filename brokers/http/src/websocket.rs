@@ -0,0 +1,228 @@
+use std::{collections::HashSet, sync::Arc};
+
+use axum::{
+	extract::ws::{self, WebSocket, WebSocketUpgrade},
+	http::HeaderMap,
+	response::{IntoResponse, Response},
+	Extension,
+};
+use futures::{SinkExt, Stream, StreamExt};
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+use spectacles::{admin, from_slice, metrics::metrics, to_vec, AnyEvent};
+use tokio::{net::TcpStream, select, time::sleep};
+use tokio_tungstenite::{
+	connect_async,
+	tungstenite::{client::IntoClientRequest, Message},
+	MaybeTlsStream, WebSocketStream,
+};
+use tracing::{debug, info, warn};
+
+use crate::{
+	auth::Auth,
+	hub::{Hub, Subscriber},
+	options::Opt,
+	output::Output,
+	retry::RetryPolicy,
+};
+
+/// A message exchanged over a WebSocket, encoded with MessagePack.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Frame<E = AnyEvent> {
+	/// Start receiving events with these names. `*` subscribes to every event.
+	Subscribe(Vec<String>),
+	/// Stop receiving events with these names.
+	Unsubscribe(Vec<String>),
+	Event(E),
+}
+
+/// The event names a connection is subscribed to.
+#[derive(Debug, Default)]
+struct Subscriptions(HashSet<String>);
+
+impl Subscriptions {
+	fn matches(&self, name: &str) -> bool {
+		self.0.contains(name) || self.0.contains("*")
+	}
+}
+
+/// Write an event received over a WebSocket to STDOUT.
+async fn write_event(output: &Output, event: &AnyEvent) {
+	debug!(?event);
+
+	match output.write(event).await {
+		Ok(()) => metrics().event_out(&event.name),
+		Err(err) => warn!(%err, "Unable to write event"),
+	}
+}
+
+/// Accept a WebSocket connection at `/ws`.
+pub async fn handle_upgrade(
+	upgrade: WebSocketUpgrade,
+	Extension(auth): Extension<Arc<Auth>>,
+	Extension(hub): Extension<Hub>,
+	Extension(output): Extension<Output>,
+	headers: HeaderMap,
+) -> Response {
+	if let Err(err) = auth.verify(&headers, "", &[]) {
+		warn!(%err, "Rejected unauthenticated WebSocket");
+		return StatusCode::UNAUTHORIZED.into_response();
+	}
+
+	upgrade.on_upgrade(move |socket| serve_socket(socket, hub.subscribe(), output))
+}
+
+/// Stream subscribed events to a connected client, and write the events it sends to STDOUT.
+async fn serve_socket(socket: WebSocket, mut subscriber: Subscriber, output: Output) {
+	let (mut sink, mut stream) = socket.split();
+	let mut subscriptions = Subscriptions::default();
+
+	loop {
+		select! {
+			message = stream.next() => match message {
+				Some(Ok(ws::Message::Binary(bytes))) => match from_slice::<Frame>(&bytes) {
+					Ok(Frame::Subscribe(names)) => subscriptions.0.extend(names),
+					Ok(Frame::Unsubscribe(names)) => {
+						for name in names {
+							subscriptions.0.remove(&name);
+						}
+					}
+					Ok(Frame::Event(event)) => write_event(&output, &event).await,
+					Err(err) => warn!(%err, "Received invalid frame"),
+				},
+				Some(Ok(ws::Message::Close(_))) | None => break,
+				Some(Ok(_)) => (),
+				Some(Err(err)) => {
+					warn!(%err, "WebSocket failed");
+					break;
+				}
+			},
+			event = subscriber.recv() => match event {
+				Some(event) if subscriptions.matches(&event.name) => {
+					let bytes = match to_vec(&Frame::Event(&*event)) {
+						Ok(bytes) => bytes,
+						Err(err) => {
+							warn!(%err, "Unable to encode event");
+							continue;
+						}
+					};
+
+					if let Err(err) = sink.send(ws::Message::Binary(bytes)).await {
+						warn!(%err, "WebSocket failed");
+						break;
+					}
+				}
+				Some(_) => (),
+				None => break,
+			},
+		}
+	}
+}
+
+/// Send events to a WebSocket server, reconnecting whenever the connection is lost, and write the
+/// events it sends to STDOUT.
+pub async fn handle_ws_out(
+	opt: Opt,
+	output: Output,
+	mut events: impl Stream<Item = AnyEvent> + Unpin,
+) -> anyhow::Result<()> {
+	let auth = Auth::from(&opt);
+	let policy = RetryPolicy::from(&opt);
+
+	let mut client = Client {
+		opt: &opt,
+		output,
+		pending: None,
+		done: false,
+	};
+	let status = admin::component("websocket");
+	let mut attempt = 0;
+
+	loop {
+		let mut request = opt.url.as_str().into_client_request()?;
+		request.headers_mut().extend(auth.headers("", &[]));
+
+		let socket = match connect_async(request).await {
+			Ok((socket, _)) => socket,
+			Err(err) => {
+				status.down();
+				attempt += 1;
+				let delay = policy.backoff(attempt);
+				warn!(%err, ?delay, "Unable to connect to WebSocket, retrying");
+				sleep(delay).await;
+				continue;
+			}
+		};
+
+		info!("Connected to {}", opt.url);
+		status.up();
+		attempt = 0;
+
+		let result = client.exchange(socket, &mut events).await;
+		status.down();
+		match result {
+			Ok(true) => return Ok(()),
+			Ok(false) => warn!("WebSocket closed, reconnecting"),
+			Err(err) => warn!(%err, "WebSocket failed, reconnecting"),
+		}
+	}
+}
+
+/// The state of the outgoing WebSocket, kept across connections.
+struct Client<'a> {
+	opt: &'a Opt,
+	output: Output,
+	/// An event read from STDIN that hasn't been sent yet.
+	pending: Option<AnyEvent>,
+	/// Whether STDIN has ended.
+	done: bool,
+}
+
+impl Client<'_> {
+	/// Exchange events over `socket` until it closes. Returns whether there is nothing left to do,
+	/// which is once STDIN ends unless there are subscriptions to keep receiving.
+	async fn exchange(
+		&mut self,
+		socket: WebSocketStream<MaybeTlsStream<TcpStream>>,
+		events: &mut (impl Stream<Item = AnyEvent> + Unpin),
+	) -> anyhow::Result<bool> {
+		let (mut sink, mut stream) = socket.split();
+
+		let subscriptions = &self.opt.subscriptions;
+		if !subscriptions.is_empty() {
+			let frame = Frame::<AnyEvent>::Subscribe(subscriptions.clone());
+			sink.send(Message::Binary(to_vec(&frame)?)).await?;
+		}
+
+		loop {
+			if let Some(event) = &self.pending {
+				sink.send(Message::Binary(to_vec(&Frame::Event(event))?))
+					.await?;
+				self.pending = None;
+			}
+
+			if self.done && subscriptions.is_empty() {
+				sink.close().await?;
+				return Ok(true);
+			}
+
+			select! {
+				event = events.next(), if !self.done => match event {
+					Some(event) => self.pending = Some(event),
+					None => self.done = true,
+				},
+				message = stream.next() => match message {
+					Some(Ok(Message::Binary(bytes))) => match from_slice::<Frame>(&bytes) {
+						Ok(Frame::Event(event)) => write_event(&self.output, &event).await,
+						Ok(_) => (),
+						Err(err) => warn!(%err, "Received invalid frame"),
+					},
+					Some(Ok(Message::Close(_))) | None => return Ok(false),
+					Some(Ok(_)) => (),
+					Some(Err(err)) => return Err(err.into()),
+				},
+			}
+		}
+	}
+}
@@ -0,0 +1,241 @@
+use anyhow::{bail, Result};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use clap::{Args, ValueEnum};
+use serde_json::{Map, Number};
+use spectacles::Value;
+
+/// The largest integer JavaScript numbers represent exactly, `Number.MAX_SAFE_INTEGER`.
+const MAX_SAFE_INTEGER: u64 = (1 << 53) - 1;
+
+/// How to write integers that JavaScript numbers can't represent exactly, like snowflakes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum LargeIntegers {
+	Number,
+	String,
+}
+
+/// How to write binary data and the data of extension values.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Binary {
+	/// An array of bytes.
+	Array,
+	/// A base64 string.
+	Base64,
+}
+
+/// How to write maps with keys that aren't strings, which JSON objects can't have.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Keys {
+	/// Fail to convert the value, so that it's skipped.
+	Reject,
+	/// Use the key's JSON text as the key, like `1` or `[1,2]`.
+	String,
+}
+
+/// Options for converting MessagePack values to JSON.
+#[derive(Debug, Clone, Copy, Args)]
+pub struct Options {
+	/// How to write integers beyond ±(2^53 - 1), which JavaScript numbers can't represent exactly.
+	#[arg(long, value_enum, default_value_t = LargeIntegers::Number)]
+	pub large_integers: LargeIntegers,
+
+	/// How to write binary data, and strings that aren't valid UTF-8.
+	#[arg(long, value_enum, default_value_t = Binary::Array)]
+	pub binary: Binary,
+
+	/// How to write maps with keys that aren't strings.
+	#[arg(long, value_enum, default_value_t = Keys::String)]
+	pub keys: Keys,
+}
+
+impl Options {
+	/// Convert a MessagePack value to JSON. Extension values are written as `[type, data]`, and
+	/// floats that aren't finite as `null`.
+	pub fn convert(&self, value: &Value) -> Result<serde_json::Value> {
+		let json = match value {
+			Value::Nil => serde_json::Value::Null,
+			Value::Boolean(bool) => serde_json::Value::Bool(*bool),
+			Value::Integer(int) => {
+				let safe = int
+					.as_i64()
+					.is_some_and(|int| int.unsigned_abs() <= MAX_SAFE_INTEGER);
+
+				match (int.as_u64(), int.as_i64()) {
+					_ if !safe && self.large_integers == LargeIntegers::String => {
+						serde_json::Value::String(int.to_string())
+					}
+					(Some(int), _) => int.into(),
+					(None, Some(int)) => int.into(),
+					(None, None) => unreachable!("integers are either u64 or i64"),
+				}
+			}
+			Value::F32(float) => self.float(f64::from(*float)),
+			Value::F64(float) => self.float(*float),
+			Value::String(string) => match string.as_str() {
+				Some(string) => serde_json::Value::String(string.to_string()),
+				None => self.binary(string.as_bytes()),
+			},
+			Value::Binary(bytes) => self.binary(bytes),
+			Value::Array(values) => serde_json::Value::Array(
+				values
+					.iter()
+					.map(|value| self.convert(value))
+					.collect::<Result<_>>()?,
+			),
+			Value::Map(entries) => serde_json::Value::Object(
+				entries
+					.iter()
+					.map(|(key, value)| Ok((self.key(key)?, self.convert(value)?)))
+					.collect::<Result<Map<_, _>>>()?,
+			),
+			Value::Ext(ty, data) => serde_json::Value::Array(vec![(*ty).into(), self.binary(data)]),
+		};
+
+		Ok(json)
+	}
+
+	fn float(&self, float: f64) -> serde_json::Value {
+		Number::from_f64(float).map_or(serde_json::Value::Null, serde_json::Value::Number)
+	}
+
+	fn binary(&self, bytes: &[u8]) -> serde_json::Value {
+		match self.binary {
+			Binary::Array => bytes.iter().copied().collect(),
+			Binary::Base64 => serde_json::Value::String(STANDARD.encode(bytes)),
+		}
+	}
+
+	fn key(&self, key: &Value) -> Result<String> {
+		if let Some(key) = key.as_str() {
+			return Ok(key.to_string());
+		}
+
+		match (self.keys, self.convert(key)?) {
+			(Keys::Reject, _) => bail!("map key {} isn't a string", key),
+			(Keys::String, serde_json::Value::String(key)) => Ok(key),
+			(Keys::String, key) => Ok(key.to_string()),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use serde_json::json;
+	use spectacles::{from_slice, Value};
+
+	use super::*;
+
+	const PLAIN: Options = Options {
+		large_integers: LargeIntegers::Number,
+		binary: Binary::Array,
+		keys: Keys::Reject,
+	};
+
+	const LOSSLESS: Options = Options {
+		large_integers: LargeIntegers::String,
+		binary: Binary::Base64,
+		keys: Keys::String,
+	};
+
+	fn convert(options: Options, value: Value) -> serde_json::Value {
+		options.convert(&value).unwrap()
+	}
+
+	#[test]
+	fn nil_and_booleans() {
+		assert_eq!(convert(PLAIN, Value::Nil), json!(null));
+		assert_eq!(convert(PLAIN, Value::from(true)), json!(true));
+	}
+
+	#[test]
+	fn integers() {
+		for options in [PLAIN, LOSSLESS] {
+			assert_eq!(convert(options, Value::from(0)), json!(0));
+			assert_eq!(convert(options, Value::from(-42)), json!(-42));
+			assert_eq!(
+				convert(options, Value::from(MAX_SAFE_INTEGER)),
+				json!(MAX_SAFE_INTEGER)
+			);
+		}
+
+		let snowflake = 1_042_345_678_901_234_567_u64;
+		assert_eq!(convert(PLAIN, Value::from(snowflake)), json!(snowflake));
+		assert_eq!(
+			convert(LOSSLESS, Value::from(snowflake)),
+			json!(snowflake.to_string())
+		);
+		assert_eq!(
+			convert(LOSSLESS, Value::from(u64::MAX)),
+			json!(u64::MAX.to_string())
+		);
+		assert_eq!(
+			convert(LOSSLESS, Value::from(i64::MIN)),
+			json!(i64::MIN.to_string())
+		);
+	}
+
+	#[test]
+	fn floats() {
+		assert_eq!(convert(PLAIN, Value::F32(1.5)), json!(1.5));
+		assert_eq!(convert(PLAIN, Value::F64(-0.25)), json!(-0.25));
+		assert_eq!(convert(PLAIN, Value::F64(f64::NAN)), json!(null));
+		assert_eq!(convert(PLAIN, Value::F32(f32::INFINITY)), json!(null));
+	}
+
+	#[test]
+	fn strings() {
+		assert_eq!(convert(PLAIN, Value::from("hi")), json!("hi"));
+
+		// A two byte string that isn't valid UTF-8.
+		let invalid = from_slice::<Value>(&[0xa2, 0xff, 0x68]).unwrap();
+		assert_eq!(convert(PLAIN, invalid.clone()), json!([255, 104]));
+		assert_eq!(convert(LOSSLESS, invalid), json!("/2g="));
+	}
+
+	#[test]
+	fn binary() {
+		let bytes = Value::Binary(vec![0, 1, 254]);
+		assert_eq!(convert(PLAIN, bytes.clone()), json!([0, 1, 254]));
+		assert_eq!(convert(LOSSLESS, bytes), json!("AAH+"));
+	}
+
+	#[test]
+	fn arrays() {
+		let array = Value::Array(vec![Value::from(1), Value::from("a"), Value::Nil]);
+		assert_eq!(convert(PLAIN, array), json!([1, "a", null]));
+	}
+
+	#[test]
+	fn maps() {
+		let map = Value::Map(vec![
+			(Value::from("id"), Value::from(1)),
+			(Value::from("tags"), Value::Array(vec![Value::from("a")])),
+		]);
+		assert_eq!(convert(PLAIN, map), json!({"id": 1, "tags": ["a"]}));
+	}
+
+	#[test]
+	fn non_string_keys() {
+		let map = Value::Map(vec![
+			(Value::from(1), Value::from("one")),
+			(Value::from(u64::MAX), Value::from("max")),
+			(Value::Nil, Value::from("nil")),
+			(
+				Value::Array(vec![Value::from(1), Value::from(2)]),
+				Value::from("pair"),
+			),
+		]);
+		assert!(PLAIN.convert(&map).is_err());
+		assert_eq!(
+			convert(LOSSLESS, map),
+			json!({"1": "one", u64::MAX.to_string(): "max", "null": "nil", "[1,2]": "pair"})
+		);
+	}
+
+	#[test]
+	fn extensions() {
+		let ext = Value::Ext(-1, vec![1, 2]);
+		assert_eq!(convert(PLAIN, ext.clone()), json!([-1, [1, 2]]));
+		assert_eq!(convert(LOSSLESS, ext), json!([-1, "AQI="]));
+	}
+}
@@ -1,15 +1,87 @@
-use std::io::{stdin, stdout, Write};
+use anyhow::Result;
+use clap::Parser;
+use futures::{SinkExt, StreamExt};
+use spectacles::{
+	init_tracing,
+	io::{read, write},
+	Event, Value,
+};
+use tokio::io::{stdin, stdout, AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tracing::warn;
 
-use serde_json::to_writer;
-use spectacles::{from_read, Value};
+use crate::convert::Options;
 
-fn main() -> anyhow::Result<()> {
-	let mut in_ = stdin();
+mod convert;
+
+#[derive(Debug, Parser)]
+#[command(name = "spectacles-json", about = env!("CARGO_PKG_DESCRIPTION"))]
+struct Opt {
+	/// Read newline-delimited JSON events from STDIN and write them to STDOUT as MessagePack,
+	/// instead of the other way around.
+	#[arg(short, long)]
+	reverse: bool,
+
+	#[command(flatten)]
+	options: Options,
+}
+
+/// Write each MessagePack value from STDIN to STDOUT as a line of JSON, until STDIN ends. Values
+/// that can't be decoded are logged and skipped.
+async fn to_json(options: Options) -> Result<()> {
+	let mut values = read::<Value>();
 	let mut out = stdout();
 
-	loop {
-		let data = from_read::<_, Value>(&mut in_)?;
-		to_writer(&mut out, &data)?;
-		out.flush()?;
+	while let Some(data) = values.next().await {
+		let json = match options.convert(&data) {
+			Ok(json) => json,
+			Err(err) => {
+				warn!(%err, "Unable to convert value to JSON");
+				continue;
+			}
+		};
+
+		let mut line = serde_json::to_vec(&json)?;
+		line.push(b'\n');
+
+		out.write_all(&line).await?;
+		out.flush().await?;
+	}
+
+	Ok(())
+}
+
+/// Write each line of JSON from STDIN to STDOUT as a MessagePack event, until STDIN ends.
+async fn from_json() -> Result<()> {
+	let mut lines = BufReader::new(stdin()).lines();
+	let mut out = write();
+
+	while let Some(line) = lines.next_line().await? {
+		if line.trim().is_empty() {
+			continue;
+		}
+
+		let event = match serde_json::from_str::<Event<serde_json::Value>>(&line) {
+			Ok(event) => event,
+			Err(err) => {
+				warn!(%err, "Received invalid event");
+				continue;
+			}
+		};
+
+		out.send(&event).await?;
+	}
+
+	Ok(())
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+	let _tracing = init_tracing();
+
+	let opt = Opt::parse();
+	if opt.reverse {
+		from_json().await
+	} else {
+		to_json(opt.options).await
 	}
 }
@@ -1,61 +1,311 @@
+use std::{collections::HashSet, sync::Arc};
+
 use anyhow::Result;
-use paho_mqtt::{Client, ConnectOptions, CreateOptions, Message};
-use spectacles::{from_read, from_slice, init_tracing, to_vec, to_writer, AnyEvent};
-use std::{
-	io::{stdin, stdout},
-	thread::spawn,
+use futures::{SinkExt, StreamExt};
+use rumqttc::{
+	v5::{
+		self,
+		mqttbytes::{
+			v5::{Filter, Packet, Publish, PublishProperties},
+			QoS,
+		},
+		AsyncClient, EventLoop,
+	},
+	Outgoing,
+};
+use spectacles::{
+	admin::{self, Status},
+	from_slice, init_tracing,
+	io::{read, write, Writer},
+	metrics::metrics,
+	to_vec, AnyEvent, Event, Value,
 };
+use tokio::{select, task::JoinHandle, time::sleep};
+use tracing::{info, warn};
 
-use crate::config::Config;
+use crate::{
+	config::{Config, ConnectOpt, PublishOpt},
+	replies::Replies,
+	topic::TopicTemplate,
+};
 
 mod config;
+mod metadata;
+mod replies;
+mod topic;
+
+/// The number of requests that may wait for the event loop before publishing has to wait.
+const REQUEST_CAPACITY: usize = 64;
+
+/// Publish events from STDIN until it ends, returning how many were published.
+async fn publish_from_stdin(
+	mqtt: AsyncClient,
+	qos: QoS,
+	template: Arc<TopicTemplate>,
+	replies: Option<Arc<Replies>>,
+	options: PublishOpt,
+) -> Result<u64> {
+	let mut events = read::<AnyEvent>();
+	let mut published = 0;
+
+	while let Some(event) = events.next().await {
+		metrics().event_in(&event.name);
+
+		let payload = match to_vec(&event.data) {
+			Ok(payload) => payload,
+			Err(err) => {
+				warn!(%err, event = %event.name, "Unable to encode event");
+				continue;
+			}
+		};
 
-fn publish_from_stdin(mqtt: Client, qos: i32) -> Result<()> {
-	let mut in_ = stdin();
+		let topic = template.topic(&event);
+		let (topic, mut properties) = match &replies {
+			Some(replies) => match replies.publication(&event, topic) {
+				Some(publication) => publication,
+				None => continue,
+			},
+			None => (topic, PublishProperties::default()),
+		};
+		properties
+			.user_properties
+			.extend(metadata::user_properties(&event));
+		properties.message_expiry_interval = options.message_expiry_interval();
+
+		let retain = options.retain.contains(&event.name);
+		mqtt.publish_with_properties(topic, qos, retain, payload, properties)
+			.await?;
+		published += 1;
+	}
 
-	loop {
-		let event = from_read::<_, AnyEvent>(&mut in_)?;
+	Ok(published)
+}
 
-		let message = Message::new(event.name, to_vec(&event.data)?, qos);
-		mqtt.publish(message)?;
+/// Write a message received from the server to STDOUT.
+async fn write_message(
+	out: &mut Writer,
+	publish: &Publish,
+	template: &TopicTemplate,
+	replies: Option<&Replies>,
+) -> Result<()> {
+	// Empty messages clear retained messages, such as the last will, rather than being events.
+	if publish.payload.is_empty() {
+		return Ok(());
 	}
+
+	let data = match from_slice::<Value>(&publish.payload) {
+		Ok(data) => data,
+		Err(err) => {
+			metrics().decode_errors.inc();
+			let topic = String::from_utf8_lossy(&publish.topic);
+			warn!(%err, %topic, "Received invalid message");
+			return Ok(());
+		}
+	};
+
+	// Messages on topics that don't match the template are named after the whole topic.
+	let topic = String::from_utf8_lossy(&publish.topic);
+	let name = template.event(&topic).unwrap_or(&topic).to_string();
+
+	let mut event = match replies {
+		Some(replies) => replies.event(publish, name, data),
+		None => Event::new(name, data),
+	};
+	metadata::read(&mut event, publish.properties.as_ref());
+
+	out.send(&event).await?;
+	metrics().event_out(&event.name);
+
+	Ok(())
+}
+
+/// Drives the connection to the MQTT server.
+struct Broker {
+	mqtt: AsyncClient,
+	events: Vec<String>,
+	group: Option<String>,
+	template: Arc<TopicTemplate>,
+	qos: QoS,
+	connect: ConnectOpt,
+	replies: Option<Arc<Replies>>,
+	/// The topic of the retained last will, if any.
+	retained_will: Option<String>,
 }
 
-fn consume_to_stdout(mqtt: Client, events: Vec<String>, qos: i32) -> Result<()> {
-	let mut out = stdout();
+impl Broker {
+	/// Run the event loop, writing messages to STDOUT and reconnecting whenever the connection is
+	/// lost, until STDIN ends and everything read from it has been delivered. Keeps receiving
+	/// messages after STDIN ends if there are events to receive.
+	async fn run(
+		self,
+		mut event_loop: EventLoop,
+		mut publisher: JoinHandle<Result<u64>>,
+	) -> Result<()> {
+		let mut out = write();
+		let status = admin::component("mqtt");
 
-	mqtt.subscribe_many(&events, &vec![qos; events.len()])?;
-	let stream = mqtt.start_consuming();
+		let mut published = None;
+		// Publications sent to the server, not counting retransmissions, and the packet IDs of
+		// those not yet acknowledged.
+		let mut sent = 0;
+		let mut unacked = HashSet::new();
+		// Publications of the broker's own, which are sent along with those from STDIN, starting
+		// with clearing the retained will once connected.
+		let mut own = u64::from(self.retained_will.is_some());
+		let mut connected = false;
+		let mut disconnecting = false;
+		let mut attempt = 0;
+
+		loop {
+			if !disconnecting
+				&& self.events.is_empty()
+				&& published.is_some_and(|published| sent >= published + own)
+				&& unacked.is_empty()
+			{
+				self.mqtt.try_disconnect()?;
+				disconnecting = true;
+			}
+
+			let event = select! {
+				result = &mut publisher, if published.is_none() => {
+					published = Some(result??);
+					continue;
+				}
+				event = event_loop.poll() => event,
+			};
+
+			match event {
+				Ok(v5::Event::Incoming(Packet::ConnAck(_))) => {
+					info!("Connected to MQTT server");
+					status.up();
+					attempt = 0;
+					self.subscribe();
+					if self.clear_will() && connected {
+						own += 1;
+					}
+					connected = true;
+				}
+				Ok(v5::Event::Incoming(Packet::Publish(publish))) => {
+					write_message(&mut out, &publish, &self.template, self.replies.as_deref())
+						.await?
+				}
+				Ok(v5::Event::Incoming(Packet::PubAck(ack))) => {
+					unacked.remove(&ack.pkid);
+				}
+				Ok(v5::Event::Incoming(Packet::PubComp(comp))) => {
+					unacked.remove(&comp.pkid);
+				}
+				// Messages retransmitted after reconnecting keep their packet IDs.
+				Ok(v5::Event::Outgoing(Outgoing::Publish(pkid))) => {
+					if self.qos == QoS::AtMostOnce || unacked.insert(pkid) {
+						sent += 1;
+					}
+				}
+				Ok(v5::Event::Outgoing(Outgoing::Disconnect)) => {
+					info!("Disconnected from MQTT server");
+					status.set(Status::Closed);
+					return Ok(());
+				}
+				Ok(_) => (),
+				Err(err) => {
+					status.down();
+					attempt += 1;
+					let delay = self.connect.reconnect_delay(attempt);
+					warn!(%err, ?delay, "Connection to MQTT server failed, reconnecting");
+					sleep(delay).await;
+				}
+			}
+		}
+	}
 
-	while let Some(message) = stream.recv()? {
-		let event = AnyEvent {
-			name: message.topic().to_owned(),
-			data: from_slice(message.payload())?,
+	/// Clear the last will retained when the previous connection was lost, since the broker is back.
+	/// Returns whether there is a will to clear.
+	fn clear_will(&self) -> bool {
+		let Some(topic) = self.retained_will.clone() else {
+			return false;
 		};
-		to_writer(&mut out, &event)?;
+
+		let mqtt = self.mqtt.clone();
+		let qos = self.qos;
+		tokio::spawn(async move {
+			if let Err(err) = mqtt.publish(topic, qos, true, Vec::new()).await {
+				warn!(%err, "Unable to clear the retained last will");
+			}
+		});
+
+		true
 	}
 
-	todo!()
+	/// Subscribe to the events to receive and to replies, which is necessary on every connection
+	/// since the server may not have kept the previous session. Events are shared with the rest of
+	/// the group if there is one, but replies are always for this broker alone.
+	fn subscribe(&self) {
+		let filters = self
+			.events
+			.iter()
+			.map(|event| {
+				let filter = self.template.filter(event);
+				match &self.group {
+					Some(group) => format!("$share/{}/{}", group, filter),
+					None => filter,
+				}
+			})
+			.chain(self.replies.as_ref().map(|replies| replies.topic.clone()))
+			.map(|topic| Filter::new(topic, self.qos))
+			.collect::<Vec<_>>();
+
+		if filters.is_empty() {
+			return;
+		}
+
+		// Subscribe from another task, since the request waits on the event loop when the request
+		// channel is full.
+		let mqtt = self.mqtt.clone();
+		tokio::spawn(async move {
+			if let Err(err) = mqtt.subscribe_many(filters).await {
+				warn!(%err, "Unable to subscribe");
+			}
+		});
+	}
 }
 
-fn main() -> Result<()> {
-	init_tracing();
+#[tokio::main]
+async fn main() -> Result<()> {
+	let _tracing = init_tracing();
 
 	let config = Config::build()?;
+	let qos = config.qos()?;
+	let template = Arc::new(config.topic()?);
+	let group = config.group()?.map(ToString::to_string);
+	let retained_will = config.retained_will_topic(&template);
 
-	let client_options = CreateOptions::from(config.create);
-	let mqtt = Client::new(client_options).unwrap();
+	let (mqtt, event_loop) = AsyncClient::new(config.mqtt_options(&template)?, REQUEST_CAPACITY);
 
-	let connect_options = ConnectOptions::try_from(config.connect)?;
-	mqtt.connect(connect_options)?;
+	if let Some(addr) = config.admin_addr {
+		admin::spawn(addr);
+	}
 
-	let mqtt_pub = mqtt.clone();
-	let handle = spawn(move || publish_from_stdin(mqtt_pub, config.qos));
+	let replies = config
+		.replies
+		.then(|| Arc::new(Replies::new(config.response_topic(), config.reply_timeout)));
 
-	if !config.events.is_empty() {
-		consume_to_stdout(mqtt, config.events, config.qos)?;
-	}
+	let publisher = tokio::spawn(publish_from_stdin(
+		mqtt.clone(),
+		qos,
+		template.clone(),
+		replies.clone(),
+		config.publish.clone(),
+	));
 
-	handle.join().unwrap()?;
-	Ok(())
+	let broker = Broker {
+		mqtt,
+		events: config.events,
+		group,
+		template,
+		qos,
+		connect: config.connect,
+		replies,
+		retained_will,
+	};
+	broker.run(event_loop, publisher).await
 }
@@ -0,0 +1,31 @@
+use rumqttc::v5::mqttbytes::v5::PublishProperties;
+use spectacles::{trace::TRACEPARENT, AnyEvent};
+
+/// The user property that carries the gateway shard an event was received on.
+pub const SHARD_PROPERTY: &str = "shard";
+
+/// The user properties that carry an event's metadata, since messages only carry its data.
+pub fn user_properties(event: &AnyEvent) -> Vec<(String, String)> {
+	let shard = event
+		.shard
+		.map(|shard| (SHARD_PROPERTY.to_string(), shard.to_string()));
+	let traceparent = event
+		.traceparent
+		.clone()
+		.map(|traceparent| (TRACEPARENT.to_string(), traceparent));
+
+	shard.into_iter().chain(traceparent).collect()
+}
+
+/// Restore the metadata of an event received with `properties`.
+pub fn read(event: &mut AnyEvent, properties: Option<&PublishProperties>) {
+	let user_properties = properties.map_or(&[][..], |properties| &properties.user_properties);
+
+	for (key, value) in user_properties {
+		match key.as_str() {
+			SHARD_PROPERTY => event.shard = value.parse().ok(),
+			TRACEPARENT => event.traceparent = Some(value.clone()),
+			_ => (),
+		}
+	}
+}
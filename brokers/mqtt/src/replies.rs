@@ -0,0 +1,236 @@
+use std::{
+	collections::{HashMap, VecDeque},
+	sync::Mutex,
+	time::{Duration, Instant},
+};
+
+use bytes::Bytes;
+use rumqttc::v5::mqttbytes::v5::{Publish, PublishProperties};
+use spectacles::{AnyEvent, Event, Value};
+use tracing::warn;
+
+/// The user property that carries the name of a reply's event, since replies are published to the
+/// requester's response topic rather than a topic named after the event.
+pub const EVENT_PROPERTY: &str = "event";
+
+/// Request/response over MQTT v5 response topics and correlation data.
+#[derive(Debug)]
+pub struct Replies {
+	/// The topic this broker receives replies on.
+	pub topic: String,
+	timeout: Duration,
+	/// Requests received from the server, waiting for a reply to their event to be read from STDIN.
+	pending: Mutex<Pending>,
+}
+
+/// Pending requests by event ID, along with their IDs in the order they were received so that
+/// expired requests can be forgotten without scanning every request.
+#[derive(Debug, Default)]
+struct Pending {
+	requests: HashMap<String, Request>,
+	received: VecDeque<(Instant, String)>,
+}
+
+/// Where to publish the reply to a request.
+#[derive(Debug)]
+struct Request {
+	response_topic: String,
+	correlation_data: Option<Bytes>,
+}
+
+impl Replies {
+	pub fn new(topic: String, timeout: Duration) -> Self {
+		Self {
+			topic,
+			timeout,
+			pending: Mutex::default(),
+		}
+	}
+
+	/// The topic and properties to publish `event` with, given the topic it would otherwise go to.
+	/// Replies to pending requests go to the requester's response topic, and events with an `id` ask
+	/// for replies on ours. Replies to requests that have expired or are unknown aren't published.
+	pub fn publication(
+		&self,
+		event: &AnyEvent,
+		topic: String,
+	) -> Option<(String, PublishProperties)> {
+		if let Some(id) = &event.reply_to {
+			let Some(request) = self.take(id) else {
+				warn!(event = %event.name, reply_to = %id, "Dropping reply to an expired or unknown request");
+				return None;
+			};
+
+			let properties = PublishProperties {
+				correlation_data: request.correlation_data,
+				user_properties: vec![(EVENT_PROPERTY.to_string(), event.name.clone())],
+				..Default::default()
+			};
+
+			return Some((request.response_topic, properties));
+		}
+
+		let properties = match &event.id {
+			Some(id) => PublishProperties {
+				response_topic: Some(self.topic.clone()),
+				correlation_data: Some(Bytes::copy_from_slice(id.as_bytes())),
+				..Default::default()
+			},
+			None => PublishProperties::default(),
+		};
+
+		Some((topic, properties))
+	}
+
+	/// The event named `name` to write to STDOUT for a message received from the server. Replies
+	/// refer to the request's `id` in their `reply_to`, and requests are given an `id` to reply to.
+	pub fn event(&self, publish: &Publish, name: String, data: Value) -> AnyEvent {
+		let topic = String::from_utf8_lossy(&publish.topic);
+		let properties = publish.properties.as_ref();
+
+		if topic == self.topic {
+			let name = properties
+				.and_then(|properties| {
+					properties
+						.user_properties
+						.iter()
+						.find(|(key, _)| key == EVENT_PROPERTY)
+				})
+				.map_or(name, |(_, name)| name.clone());
+			let reply_to = properties
+				.and_then(|properties| properties.correlation_data.as_ref())
+				.map(|data| String::from_utf8_lossy(data).into_owned());
+
+			return Event {
+				reply_to,
+				..Event::new(name, data)
+			};
+		}
+
+		let id = properties.and_then(|properties| {
+			let response_topic = properties.response_topic.clone()?;
+			Some(self.insert(response_topic, properties.correlation_data.clone()))
+		});
+
+		Event {
+			id,
+			..Event::new(name, data)
+		}
+	}
+
+	/// Generate an event ID for a request to be replied to on `response_topic`. Requests that
+	/// haven't been replied to within the timeout are forgotten.
+	fn insert(&self, response_topic: String, correlation_data: Option<Bytes>) -> String {
+		let id = format!("{:032x}", rand::random::<u128>());
+		let request = Request {
+			response_topic,
+			correlation_data,
+		};
+
+		let mut pending = self.pending.lock().unwrap();
+		self.expire(&mut pending);
+		pending.requests.insert(id.clone(), request);
+		pending.received.push_back((Instant::now(), id.clone()));
+
+		id
+	}
+
+	/// Take the request with the event ID `id`, if it is still waiting for a reply.
+	fn take(&self, id: &str) -> Option<Request> {
+		let mut pending = self.pending.lock().unwrap();
+		self.expire(&mut pending);
+		pending.requests.remove(id)
+	}
+
+	/// Forget the requests that haven't been replied to within the timeout, oldest first.
+	fn expire(&self, pending: &mut Pending) {
+		while let Some((received_at, _)) = pending.received.front() {
+			if received_at.elapsed() < self.timeout {
+				break;
+			}
+
+			let (_, id) = pending.received.pop_front().unwrap();
+			pending.requests.remove(&id);
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::thread::sleep;
+
+	use super::*;
+
+	const RESPONSE_TOPIC: &str = "requester/responses";
+
+	fn request(replies: &Replies) -> String {
+		replies.insert(
+			RESPONSE_TOPIC.to_string(),
+			Some(Bytes::from_static(b"correlation")),
+		)
+	}
+
+	fn reply(id: &str) -> AnyEvent {
+		Event {
+			reply_to: Some(id.to_string()),
+			..Event::new("REPLY".to_string(), Value::Nil)
+		}
+	}
+
+	#[test]
+	fn replies_go_to_the_response_topic() {
+		let replies = Replies::new("responses".to_string(), Duration::from_secs(30));
+		let id = request(&replies);
+
+		let (topic, properties) = replies
+			.publication(&reply(&id), "REPLY".to_string())
+			.unwrap();
+		assert_eq!(topic, RESPONSE_TOPIC);
+		assert_eq!(
+			properties.correlation_data,
+			Some(Bytes::from_static(b"correlation"))
+		);
+		assert_eq!(
+			properties.user_properties,
+			[(EVENT_PROPERTY.to_string(), "REPLY".to_string())]
+		);
+
+		// A request is only replied to once.
+		assert!(replies
+			.publication(&reply(&id), "REPLY".to_string())
+			.is_none());
+	}
+
+	#[test]
+	fn expired_replies_are_dropped() {
+		let replies = Replies::new("responses".to_string(), Duration::from_millis(20));
+		let expired = request(&replies);
+		sleep(Duration::from_millis(30));
+		let pending = request(&replies);
+
+		assert_eq!(replies.pending.lock().unwrap().requests.len(), 1);
+		assert!(replies
+			.publication(&reply(&expired), "REPLY".to_string())
+			.is_none());
+		assert!(replies
+			.publication(&reply("unknown"), "REPLY".to_string())
+			.is_none());
+		assert!(replies
+			.publication(&reply(&pending), "REPLY".to_string())
+			.is_some());
+	}
+
+	#[test]
+	fn requests_ask_for_replies() {
+		let replies = Replies::new("responses".to_string(), Duration::from_secs(30));
+		let event = Event {
+			id: Some("1".to_string()),
+			..Event::new("REQUEST".to_string(), Value::Nil)
+		};
+
+		let (topic, properties) = replies.publication(&event, "REQUEST".to_string()).unwrap();
+		assert_eq!(topic, "REQUEST");
+		assert_eq!(properties.response_topic.as_deref(), Some("responses"));
+		assert_eq!(properties.correlation_data, Some(Bytes::from_static(b"1")));
+	}
+}
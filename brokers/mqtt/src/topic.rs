@@ -0,0 +1,213 @@
+use std::str::FromStr;
+
+use anyhow::{bail, Error, Result};
+use spectacles::{AnyEvent, Value};
+
+/// Maps event names to topics, like `discord/{shard}/{event}`. Each level of the template is either
+/// literal, the event name, the gateway shard, or a top-level field of the event's data.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TopicTemplate {
+	levels: Vec<Level>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Level {
+	Literal(String),
+	Event,
+	Shard,
+	Field(String),
+}
+
+impl FromStr for TopicTemplate {
+	type Err = Error;
+
+	fn from_str(template: &str) -> Result<Self> {
+		let levels = template
+			.split('/')
+			.map(|level| {
+				match level
+					.strip_prefix('{')
+					.and_then(|level| level.strip_suffix('}'))
+				{
+					Some("event") => Ok(Level::Event),
+					Some("shard") => Ok(Level::Shard),
+					Some(field) if !field.is_empty() => Ok(Level::Field(field.to_string())),
+					_ if level.contains(['{', '}', '+', '#']) => {
+						bail!(
+							"invalid topic level {:?}: placeholders must be whole levels",
+							level
+						)
+					}
+					_ => Ok(Level::Literal(level.to_string())),
+				}
+			})
+			.collect::<Result<Vec<_>>>()?;
+
+		if levels
+			.iter()
+			.filter(|level| **level == Level::Event)
+			.count() != 1
+		{
+			bail!("topic template must contain {{event}} exactly once");
+		}
+
+		Ok(Self { levels })
+	}
+}
+
+impl TopicTemplate {
+	/// The topic to publish `event` to. A missing shard or field is left empty, and separators and
+	/// wildcards in the event name are replaced, since they can't be published to.
+	pub fn topic(&self, event: &AnyEvent) -> String {
+		let shard = event
+			.shard
+			.map_or_else(String::new, |shard| shard.to_string());
+		self.render(&sanitize_level(&event.name), &shard, |field| {
+			field_value(&event.data, field).map_or_else(String::new, sanitize)
+		})
+	}
+
+	/// The filter to subscribe to `event` with, matching any value of the other fields. `event` is
+	/// used as is if it is already a filter over several levels, such as `discord/#`.
+	pub fn filter(&self, event: &str) -> String {
+		if event.contains(['/', '#']) {
+			return event.to_string();
+		}
+
+		self.render(event, "+", |_| "+".to_string())
+	}
+
+	/// The name of the event published to `topic`, if it matches the template.
+	pub fn event<'a>(&self, topic: &'a str) -> Option<&'a str> {
+		let levels = topic.split('/').collect::<Vec<_>>();
+		if levels.len() != self.levels.len() {
+			return None;
+		}
+
+		let mut event = None;
+		for (template, level) in self.levels.iter().zip(levels) {
+			match template {
+				Level::Literal(literal) if literal != level => return None,
+				Level::Event => event = Some(level),
+				_ => (),
+			}
+		}
+
+		event
+	}
+
+	fn render(&self, event: &str, shard: &str, field: impl Fn(&str) -> String) -> String {
+		self.levels
+			.iter()
+			.map(|level| match level {
+				Level::Literal(literal) => literal.clone(),
+				Level::Event => event.to_string(),
+				Level::Shard => shard.to_string(),
+				Level::Field(name) => field(name),
+			})
+			.collect::<Vec<_>>()
+			.join("/")
+	}
+}
+
+fn field_value<'a>(data: &'a Value, field: &str) -> Option<&'a Value> {
+	data.as_map()?
+		.iter()
+		.find(|(key, _)| key.as_str() == Some(field))
+		.map(|(_, value)| value)
+}
+
+/// Render a field as a single topic level, without separators or wildcards.
+fn sanitize(value: &Value) -> String {
+	let value = match value {
+		Value::String(string) => string.as_str().unwrap_or_default().to_string(),
+		Value::Integer(int) => int.to_string(),
+		Value::Boolean(bool) => bool.to_string(),
+		_ => String::new(),
+	};
+
+	sanitize_level(&value)
+}
+
+/// Replace the separators and wildcards in `level`, so it's a single level that can be published
+/// to.
+fn sanitize_level(level: &str) -> String {
+	level.replace(['/', '+', '#'], "_")
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn template(template: &str) -> TopicTemplate {
+		template.parse().unwrap()
+	}
+
+	fn event(name: &str, data: Value) -> AnyEvent {
+		AnyEvent::new(name.to_string(), data)
+	}
+
+	#[test]
+	fn event_placeholder_is_required_once() {
+		assert!("bot/{event}/{event}".parse::<TopicTemplate>().is_err());
+		assert!("bot/{guild_id}".parse::<TopicTemplate>().is_err());
+		assert!("bot".parse::<TopicTemplate>().is_err());
+		assert!("{event}".parse::<TopicTemplate>().is_ok());
+	}
+
+	#[test]
+	fn placeholders_are_whole_levels() {
+		for invalid in [
+			"bot-{event}",
+			"bot/{event",
+			"bot/event}/{event}",
+			"bot/{}/{event}",
+			"bot/+/{event}",
+			"bot/#/{event}",
+			"bot/{event}/#",
+		] {
+			assert!(invalid.parse::<TopicTemplate>().is_err(), "{}", invalid);
+		}
+	}
+
+	#[test]
+	fn topics_are_rendered() {
+		let template = template("bot/{shard}/{guild_id}/{event}");
+		let data = Value::Map(vec![("guild_id".into(), 1234.into())]);
+		let mut create = event("MESSAGE_CREATE", data);
+		assert_eq!(template.topic(&create), "bot//1234/MESSAGE_CREATE");
+
+		create.shard = Some(3);
+		assert_eq!(template.topic(&create), "bot/3/1234/MESSAGE_CREATE");
+
+		let ready = event("READY", Value::Nil);
+		assert_eq!(template.topic(&ready), "bot///READY");
+	}
+
+	#[test]
+	fn topics_have_no_wildcards() {
+		let template = template("bot/{guild_id}/{event}");
+		let data = Value::Map(vec![("guild_id".into(), "a/+/#".into())]);
+		assert_eq!(template.topic(&event("b/+/#", data)), "bot/a____/b____");
+	}
+
+	#[test]
+	fn filters_match_any_field() {
+		let template = template("bot/{shard}/{guild_id}/{event}");
+		assert_eq!(template.filter("MESSAGE_CREATE"), "bot/+/+/MESSAGE_CREATE");
+		assert_eq!(template.filter("+"), "bot/+/+/+");
+		assert_eq!(template.filter("bot/1234/#"), "bot/1234/#");
+	}
+
+	#[test]
+	fn events_round_trip() {
+		let template = template("bot/{guild_id}/{event}/{shard}");
+		let data = Value::Map(vec![("guild_id".into(), 1234.into())]);
+		let topic = template.topic(&event("MESSAGE_CREATE", data));
+		assert_eq!(template.event(&topic), Some("MESSAGE_CREATE"));
+
+		assert_eq!(template.event("bot/1234/MESSAGE_CREATE"), None);
+		assert_eq!(template.event("other/1234/MESSAGE_CREATE/0"), None);
+		assert_eq!(template.event("bot/1234/MESSAGE_CREATE/0/extra"), None);
+	}
+}
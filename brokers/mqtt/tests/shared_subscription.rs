@@ -0,0 +1,87 @@
+//! Runs against the MQTT 5 server at `MQTT_TEST_URL` (default `localhost:1883`), such as Mosquitto
+//! or EMQX: `cargo test -p spectacles-mqtt -- --ignored`.
+
+use std::{
+	env,
+	io::Write,
+	process::{Command, Stdio},
+	thread::sleep,
+	time::Duration,
+};
+
+use spectacles::{from_read, to_vec, AnyEvent, Event, Value};
+
+const EVENTS: u64 = 20;
+
+fn broker(args: &[&str]) -> Command {
+	let url = env::var("MQTT_TEST_URL").unwrap_or_else(|_| "localhost:1883".to_string());
+
+	let mut command = Command::new(env!("CARGO_BIN_EXE_spectacles-mqtt"));
+	command.arg("--url").arg(url).args(args);
+	command
+}
+
+fn decode(mut bytes: &[u8]) -> Vec<AnyEvent> {
+	let mut events = Vec::new();
+	while !bytes.is_empty() {
+		events.push(from_read(&mut bytes).expect("invalid event"));
+	}
+
+	events
+}
+
+#[test]
+#[ignore = "requires an MQTT server"]
+fn shared_subscription_splits_events() {
+	let id = rand::random::<u64>();
+	let topic = format!("spectacles-test/{:x}/{{event}}", id);
+	let group = format!("spectacles-test-{:x}", id);
+
+	let workers = (0..2)
+		.map(|_| {
+			broker(&["--topic", &topic, "--group", &group, "--events", "WORK"])
+				.stdin(Stdio::null())
+				.stdout(Stdio::piped())
+				.spawn()
+				.expect("unable to start worker")
+		})
+		.collect::<Vec<_>>();
+	sleep(Duration::from_secs(1));
+
+	let mut publisher = broker(&["--topic", &topic])
+		.stdin(Stdio::piped())
+		.spawn()
+		.expect("unable to start publisher");
+	let mut stdin = publisher.stdin.take().unwrap();
+	for i in 0..EVENTS {
+		stdin
+			.write_all(&to_vec(&Event::new("WORK", Value::from(i))).unwrap())
+			.unwrap();
+	}
+	drop(stdin);
+	assert!(publisher.wait().unwrap().success());
+	sleep(Duration::from_secs(1));
+
+	let received = workers
+		.into_iter()
+		.map(|mut worker| {
+			worker.kill().unwrap();
+			decode(&worker.wait_with_output().unwrap().stdout)
+		})
+		.collect::<Vec<_>>();
+
+	for events in &received {
+		assert!(!events.is_empty(), "a worker received no events");
+	}
+
+	let mut data = received
+		.iter()
+		.flatten()
+		.map(|event| {
+			assert_eq!(event.name, "WORK");
+			event.data.as_u64().unwrap()
+		})
+		.collect::<Vec<_>>();
+	data.sort_unstable();
+	assert_eq!(data, (0..EVENTS).collect::<Vec<_>>());
+}
@@ -0,0 +1,35 @@
+//! Consume typed events from Redis, acknowledging each one once it has been handled.
+//!
+//! ```sh
+//! cargo run --example consume
+//! ```
+
+use anyhow::Result;
+use futures::TryStreamExt;
+use serde::Deserialize;
+use spectacles_redis::{
+	redust::pool::{Manager, Pool},
+	Client,
+};
+
+#[derive(Debug, Deserialize)]
+struct Greeting {
+	message: String,
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+	let pool = Pool::builder(Manager::new("localhost:6379".to_string())).build()?;
+	let client = Client::new("example", pool);
+
+	let events = ["GREETING"];
+	client.ensure_events(events.iter()).await?;
+
+	let mut messages = client.consume::<Greeting, _, _>(events);
+	while let Some(message) = messages.try_next().await? {
+		println!("{}: {}", message.id, message.data.message);
+		message.ack().await?;
+	}
+
+	Ok(())
+}
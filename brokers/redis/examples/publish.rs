@@ -0,0 +1,31 @@
+//! Publish a typed event to Redis.
+//!
+//! ```sh
+//! cargo run --example publish
+//! ```
+
+use anyhow::Result;
+use serde::Serialize;
+use spectacles_redis::{
+	redust::pool::{Manager, Pool},
+	Client,
+};
+
+#[derive(Debug, Serialize)]
+struct Greeting {
+	message: String,
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+	let pool = Pool::builder(Manager::new("localhost:6379".to_string())).build()?;
+	let client = Client::new("example", pool);
+
+	let greeting = Greeting {
+		message: "hello world".to_string(),
+	};
+	let id = client.publish("GREETING", &greeting).await?;
+
+	println!("published {:?} as {}", greeting, id);
+	Ok(())
+}
@@ -0,0 +1,44 @@
+//! Make a request and wait for its reply. Both sides of the exchange run in this example; in
+//! practice, the responder is usually a separate service.
+//!
+//! ```sh
+//! cargo run --example request
+//! ```
+
+use std::time::Duration;
+
+use anyhow::Result;
+use futures::TryStreamExt;
+use spectacles_redis::{
+	redust::pool::{Manager, Pool},
+	Client,
+};
+
+async fn respond(client: Client) -> Result<()> {
+	let events = ["ADD"];
+	let mut messages = client.consume::<(i64, i64), _, _>(events);
+
+	while let Some(message) = messages.try_next().await? {
+		let (a, b) = message.data;
+		message.reply(&(a + b)).await?;
+		message.ack().await?;
+	}
+
+	Ok(())
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+	let pool = Pool::builder(Manager::new("localhost:6379".to_string())).build()?;
+	let client = Client::new("example", pool);
+
+	client.ensure_events(["ADD"].iter()).await?;
+	tokio::spawn(respond(client.clone()));
+
+	let sum: i64 = client
+		.request("ADD", &(1, 2), Duration::from_secs(5))
+		.await?;
+
+	println!("1 + 2 = {}", sum);
+	Ok(())
+}
@@ -1,40 +1,59 @@
 use std::{
 	borrow::Cow,
 	fmt::Debug,
+	io::Write,
+	pin::pin,
 	sync::{Arc, RwLock},
 	time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
-use anyhow::{Error, Result};
+use anyhow::{anyhow, Error, Result};
 use bytes::Bytes;
 use futures::{
-	stream::{iter, poll_fn, repeat_with, select, select_all},
+	future::ready,
+	stream::{iter, poll_fn, repeat_with, select, select_all, try_unfold},
 	Future, Stream, StreamExt, TryFutureExt, TryStream, TryStreamExt,
 };
 use nanoid::nanoid;
 use redust::{
-	model::stream::{
-		claim::AutoclaimResponse,
-		read::{Entries, Field, ReadResponse},
-		Id,
+	model::{
+		pubsub::Response,
+		stream::{
+			claim::AutoclaimResponse,
+			read::{Entries, Entry, Field, ReadResponse},
+			Id,
+		},
 	},
-	pool::Pool,
+	pool::{deadpool::managed::Object, Pool},
 	resp::from_data,
 };
-use spectacles::{to_vec, Value};
-use tokio::time::sleep;
-
-use self::message::Message;
+use serde::{de::DeserializeOwned, Serialize};
+use spectacles::{from_slice, metrics::metrics, to_vec, trace, Event};
+use tokio::time::{sleep, timeout};
+use tracing::warn;
+
+use self::{
+	info::StreamInfo,
+	message::{decode_data, Message},
+	position::Position,
+	tiers::Tiers,
+};
 
+pub mod info;
 pub mod message;
+pub mod position;
+mod tiers;
 
 const DEFAULT_MAX_CHUNK: &[u8] = b"10";
 const DEFAULT_BLOCK_INTERVAL: &[u8] = b"5000";
 const DEFAULT_BLOCK_DURATION: Duration = Duration::from_secs(5);
 const DEFAULT_MIN_IDLE_TIME: &[u8] = b"10000";
+const RANGE_CHUNK: usize = 100;
 pub const STREAM_DATA_KEY: Field<'static> = Field(Cow::Borrowed(b"data"));
 pub const STREAM_TIMEOUT_KEY: Field<'static> = Field(Cow::Borrowed(b"timeout_at"));
+pub const STREAM_TRACEPARENT_KEY: Field<'static> = Field(Cow::Borrowed(b"traceparent"));
 
+/// Repeatedly call `func`, yielding the output of each future until one resolves to [`None`].
 pub fn repeat_fn<F, R, O>(mut func: F) -> impl Stream<Item = O>
 where
 	R: Future<Output = Option<O>>,
@@ -53,6 +72,7 @@ where
 	})
 }
 
+/// A client for publishing and consuming events in a single consumer group.
 #[derive(Clone)]
 pub struct Client {
 	pub name: Bytes,
@@ -71,6 +91,8 @@ impl Debug for Client {
 }
 
 impl Client {
+	/// Create a new client in `group`. Every client is given a unique consumer name within the
+	/// group.
 	pub fn new(group: impl Into<Bytes>, pool: Pool<String>) -> Self {
 		let group = group.into();
 		let name = nanoid!().into();
@@ -83,27 +105,72 @@ impl Client {
 		}
 	}
 
-	pub async fn publish(&self, event: impl AsRef<str>, data: &Value) -> Result<Id> {
+	/// Check that Redis can be reached.
+	pub async fn ping(&self) -> Result<()> {
 		let mut conn = self.pool.get().await?;
-		let data = conn
-			.cmd([
-				b"XADD".as_slice(),
-				event.as_ref().as_bytes(),
-				b"*",
-				&STREAM_DATA_KEY.0,
-				&to_vec(data)?,
-			])
-			.await?;
+		conn.cmd([b"PING"]).await?;
+
+		Ok(())
+	}
 
+	/// Read the length of the stream for `event` and how many of its entries are pending in this
+	/// group.
+	pub async fn stream_info(&self, event: impl AsRef<str>) -> Result<StreamInfo> {
+		let event = event.as_ref().as_bytes();
+
+		let mut conn = self.pool.get().await?;
+		let stream = conn.cmd([b"XINFO".as_slice(), b"STREAM", event]).await?;
+		let groups = conn.cmd([b"XINFO".as_slice(), b"GROUPS", event]).await?;
+
+		Ok(StreamInfo::from_replies(&stream, &groups, &self.group))
+	}
+
+	/// Publish `data` to the stream for `event`, continuing the trace of the current span.
+	pub async fn publish<T>(&self, event: impl AsRef<str>, data: &T) -> Result<Id>
+	where
+		T: Serialize + ?Sized,
+	{
+		self.publish_traced(event, data, trace::traceparent().as_deref())
+			.await
+	}
+
+	/// Publish `data` to the stream for `event`, continuing the trace of `traceparent`.
+	pub async fn publish_traced<T>(
+		&self,
+		event: impl AsRef<str>,
+		data: &T,
+		traceparent: Option<&str>,
+	) -> Result<Id>
+	where
+		T: Serialize + ?Sized,
+	{
+		let data = to_vec(data)?;
+		let mut cmd = vec![
+			b"XADD".as_slice(),
+			event.as_ref().as_bytes(),
+			b"*",
+			&STREAM_DATA_KEY.0,
+			&data,
+		];
+		if let Some(traceparent) = traceparent {
+			cmd.extend([&*STREAM_TRACEPARENT_KEY.0, traceparent.as_bytes()]);
+		}
+
+		let data = self.pool.get().await?.cmd(&cmd).await?;
 		Ok(from_data(data)?)
 	}
 
-	pub async fn publish_timeout(
+	/// Publish `data` to the stream for `event`, indicating to consumers that work on it should be
+	/// cancelled after `timeout`.
+	pub async fn publish_timeout<T>(
 		&self,
 		event: impl AsRef<str>,
-		data: impl AsRef<[u8]>,
+		data: &T,
 		timeout: SystemTime,
-	) -> Result<Id> {
+	) -> Result<Id>
+	where
+		T: Serialize + ?Sized,
+	{
 		let mut conn = self.pool.get().await?;
 
 		let timeout_bytes = timeout
@@ -113,26 +180,152 @@ impl Client {
 			.to_string()
 			.into_bytes();
 
-		let data = conn
+		let data = to_vec(data)?;
+		let traceparent = trace::traceparent();
+		let mut cmd = vec![
+			b"XADD".as_slice(),
+			event.as_ref().as_bytes(),
+			b"*",
+			&STREAM_DATA_KEY.0,
+			&data,
+			&STREAM_TIMEOUT_KEY.0,
+			&timeout_bytes,
+		];
+		if let Some(traceparent) = &traceparent {
+			cmd.extend([&*STREAM_TRACEPARENT_KEY.0, traceparent.as_bytes()]);
+		}
+
+		let data = conn.cmd(&cmd).await?;
+
+		Ok(from_data(data)?)
+	}
+
+	/// Publish `data` to the stream for `event` and wait for a consumer to [reply](Message::reply)
+	/// to it. Fails if no reply is received within `timeout`.
+	pub async fn request<T, R>(
+		&self,
+		event: impl AsRef<str>,
+		data: &T,
+		timeout_after: Duration,
+	) -> Result<R>
+	where
+		T: Serialize + ?Sized,
+		R: DeserializeOwned,
+	{
+		let event = event.as_ref();
+
+		// Subscribe before publishing so that a fast reply cannot be missed. The connection is
+		// taken out of the pool since it can't be reused once in PubSub mode.
+		let mut conn = Object::take(self.pool.get().await?);
+		let pattern = format!("{}:*", event);
+		conn.cmd([b"PSUBSCRIBE", pattern.as_bytes()]).await?;
+
+		let id = self
+			.publish_timeout(event, data, SystemTime::now() + timeout_after)
+			.await?;
+		let channel = reply_channel(event.as_bytes(), &id);
+
+		let replies = conn.map_err(Error::from).try_filter_map(|data| {
+			let reply = from_data::<Response>(data)
+				.map_err(Error::from)
+				.map(|response| match response {
+					Response::Message(message) if *message.channel == *channel => {
+						Some(message.data.into_owned())
+					}
+					_ => None,
+				});
+
+			ready(reply)
+		});
+
+		let data = timeout(timeout_after, pin!(replies).try_next())
+			.await
+			.map_err(|_| anyhow!("timed out waiting for reply to {}", id))??
+			.ok_or_else(|| anyhow!("connection closed waiting for reply to {}", id))?;
+
+		Ok(from_slice(&data)?)
+	}
+
+	/// Publish `data` to subscribers of `event` using PubSub. Unlike [`publish`](Self::publish),
+	/// the event is not persisted: it is received by every client [subscribed](Self::subscribe) at
+	/// the time it is published and by no one else. The message is only the data, so no traceparent
+	/// is sent with it.
+	pub async fn broadcast<T>(&self, event: impl AsRef<str>, data: &T) -> Result<()>
+	where
+		T: Serialize + ?Sized,
+	{
+		self.pool
+			.get()
+			.await?
 			.cmd([
-				b"XADD",
+				b"PUBLISH".as_slice(),
 				event.as_ref().as_bytes(),
-				b"*",
-				&STREAM_DATA_KEY.0,
-				data.as_ref(),
-				&STREAM_TIMEOUT_KEY.0,
-				&timeout_bytes,
+				&to_vec(data)?,
 			])
 			.await?;
 
-		Ok(from_data(data)?)
+		Ok(())
+	}
+
+	/// Subscribe to [broadcast](Self::broadcast) events on channels matching any of `patterns`,
+	/// decoding their data as `D`. Patterns use Redis glob-style syntax, e.g. `GUILD_*`.
+	pub async fn subscribe<D, T, U>(
+		&self,
+		patterns: T,
+	) -> Result<impl TryStream<Ok = Event<D>, Error = Error> + Unpin>
+	where
+		D: DeserializeOwned,
+		T: AsRef<[U]>,
+		U: AsRef<[u8]>,
+	{
+		let mut conn = Object::take(self.pool.get().await?);
+
+		let mut cmd: Vec<&[u8]> = vec![b"PSUBSCRIBE"];
+		cmd.extend(patterns.as_ref().iter().map(|p| p.as_ref()));
+		conn.send_cmd(cmd).await?;
+
+		let events = conn.map_err(Error::from).try_filter_map(|data| {
+			let event = from_data::<Response>(data)
+				.map_err(Error::from)
+				.map(|response| match response {
+					Response::Message(message) => {
+						let name = String::from_utf8_lossy(&message.channel);
+						match from_slice(&message.data) {
+							Ok(data) => Some(Event::new(name, data)),
+							Err(err) => {
+								metrics().decode_errors.inc();
+								warn!(%err, event = %name, "Skipping undecodable message");
+								None
+							}
+						}
+					}
+					_ => None,
+				});
+
+			ready(event)
+		});
+
+		Ok(events)
 	}
 
+	/// Ensure the consumer group exists for each of `events`, creating the streams if necessary.
+	/// New groups only receive events published after they are created.
 	pub async fn ensure_events(
 		&self,
 		events: impl Iterator<Item = impl AsRef<[u8]>>,
+	) -> Result<()> {
+		self.ensure_events_at(events, Position::End).await
+	}
+
+	/// Ensure the consumer group exists for each of `events`, creating the streams if necessary.
+	/// New groups receive every event after `position`; existing groups are left unchanged.
+	pub async fn ensure_events_at(
+		&self,
+		events: impl Iterator<Item = impl AsRef<[u8]>>,
+		position: Position,
 	) -> Result<()> {
 		let mut conn = self.pool.get().await?;
+		let id = position.group_id();
 
 		for event in events {
 			let cmd: &[&[u8]] = &[
@@ -140,7 +333,7 @@ impl Client {
 				b"CREATE",
 				event.as_ref(),
 				&*self.group,
-				b"$",
+				id.as_bytes(),
 				b"MKSTREAM",
 			];
 
@@ -154,58 +347,260 @@ impl Client {
 		Ok(())
 	}
 
-	/// Consume events from the broker.
-	pub fn consume<'s, T, U>(
+	/// Move the consumer group for each of `events` to `position`, so that the group is delivered
+	/// every event after it again. Pending messages are unaffected.
+	pub async fn reset_events(
+		&self,
+		events: impl Iterator<Item = impl AsRef<[u8]>>,
+		position: Position,
+	) -> Result<()> {
+		let mut conn = self.pool.get().await?;
+		let id = position.group_id();
+
+		for event in events {
+			let cmd: &[&[u8]] = &[
+				b"XGROUP",
+				b"SETID",
+				event.as_ref(),
+				&*self.group,
+				id.as_bytes(),
+			];
+
+			conn.cmd(cmd).await?;
+		}
+
+		Ok(())
+	}
+
+	/// Read the events in the stream for `event` between `start` and `end` (inclusive), decoding
+	/// their data as `D`. Events are read outside of any consumer group, so they are not
+	/// acknowledged or claimed.
+	pub fn range<'s, D>(
 		&'s self,
-		events: T,
-	) -> impl TryStream<Ok = Message, Error = Error> + Unpin + 's
+		event: impl Into<Bytes>,
+		start: Position,
+		end: Position,
+	) -> impl TryStream<Ok = (Id, D), Error = Error> + Unpin + 's
 	where
-		T: AsRef<[U]> + Clone + 's,
-		U: AsRef<[u8]> + 's,
+		D: DeserializeOwned + 's,
 	{
-		let autoclaim = self.autoclaim_all(events.clone()).into_stream();
-		let claim = self.claim(events).into_stream();
+		let event = event.into();
+		let end = end.range_id();
+
+		let pages = try_unfold(Some(start.range_id()), move |start| {
+			let event = event.clone();
+			let end = end.clone();
+
+			async move {
+				let Some(start) = start else {
+					return Ok(None);
+				};
+
+				let count = RANGE_CHUNK.to_string();
+				let cmd: &[&[u8]] = &[
+					b"XRANGE",
+					&event,
+					start.as_bytes(),
+					end.as_bytes(),
+					b"COUNT",
+					count.as_bytes(),
+				];
+				let data = self.pool.get().await?.cmd(cmd).await?;
+				let entries = from_data::<Vec<(Id, Entry)>>(data)?;
+
+				// Continue after the last entry only if this page was full.
+				let next = entries
+					.last()
+					.filter(|_| entries.len() == RANGE_CHUNK)
+					.map(|(id, _)| format!("({}", id));
+
+				// Skip entries that can't be decoded rather than ending the range on them.
+				let page = entries
+					.into_iter()
+					.filter_map(|(id, entry)| match decode_data(&id, &entry) {
+						Ok(data) => Some(Ok((id, data))),
+						Err(err) => {
+							warn!(%err, %id, "Skipping undecodable message");
+							None
+						}
+					})
+					.collect::<Vec<_>>();
+
+				Ok::<_, Error>(Some((iter(page), next)))
+			}
+		});
 
-		select(autoclaim, claim)
+		Box::pin(pages.try_flatten())
 	}
 
-	fn claim<'s, T, U>(
+	/// Consume events from the broker, decoding their data as `D`.
+	pub fn consume<'s, D, T, U>(
 		&'s self,
 		events: T,
-	) -> impl TryStream<Ok = Message, Error = Error> + Unpin + 's
+	) -> impl TryStream<Ok = Message<D>, Error = Error> + Unpin + 's
 	where
-		T: AsRef<[U]> + Clone + 's,
+		D: DeserializeOwned + 's,
+		T: AsRef<[U]>,
 		U: AsRef<[u8]>,
+	{
+		let events = events
+			.as_ref()
+			.iter()
+			.map(|event| Bytes::copy_from_slice(event.as_ref()))
+			.collect::<Vec<_>>();
+
+		self.consume_tiers(Tiers::from_tiers(vec![events]))
+	}
+
+	/// Consume events from the broker, decoding their data as `D`. Each event is given a priority:
+	/// new messages are read from the highest priority events that have any, so a flood of low
+	/// priority events can't delay higher priority ones. Events with the same priority are read
+	/// fairly, with their messages interleaved. To keep lower priorities from starving, a tier of
+	/// events passed over for ten reads in a row is read first.
+	pub fn consume_prioritized<'s, D, I, E>(
+		&'s self,
+		events: I,
+	) -> impl TryStream<Ok = Message<D>, Error = Error> + Unpin + 's
+	where
+		D: DeserializeOwned + 's,
+		I: IntoIterator<Item = (E, u32)>,
+		E: AsRef<[u8]>,
+	{
+		self.consume_tiers(Tiers::new(events))
+	}
+
+	/// Consume events from each tier, in descending order of priority.
+	fn consume_tiers<'s, D>(
+		&'s self,
+		tiers: Tiers,
+	) -> impl TryStream<Ok = Message<D>, Error = Error> + Unpin + 's
+	where
+		D: DeserializeOwned + 's,
+	{
+		let events = tiers.events.concat();
+
+		let autoclaim = self.autoclaim_all(events).into_stream();
+		let claim = self.claim(Arc::new(tiers)).into_stream();
+
+		select(autoclaim, claim)
+	}
+
+	fn claim<'s, D>(
+		&'s self,
+		tiers: Arc<Tiers>,
+	) -> impl TryStream<Ok = Message<D>, Error = Error> + Unpin + 's
+	where
+		D: DeserializeOwned + 's,
 	{
 		let fut_fn = move || {
-			self.get_messages(events.clone())
-				.map_ok(|msgs| iter(msgs.map(Ok)))
+			self.get_messages(tiers.clone())
+				.map_ok(iter)
 				.try_flatten_stream()
 		};
 
 		Box::pin(repeat_with(fut_fn).flatten())
 	}
 
-	async fn get_messages<'s, T, U>(
+	/// Get new messages from the first tier of events in [`Tiers::order`] that has any. Only
+	/// blocks, on every event at once, when no tier has messages available.
+	async fn get_messages<'s, D>(
 		&'s self,
-		events: T,
-	) -> Result<impl Iterator<Item = Message> + Unpin + 's>
+		tiers: Arc<Tiers>,
+	) -> Result<impl Iterator<Item = Result<Message<D>>>>
 	where
-		T: AsRef<[U]>,
-		U: AsRef<[u8]>,
+		D: DeserializeOwned + 's,
 	{
-		let read = self.xreadgroup(events).await?.unwrap_or_default();
+		let mut read = None;
+		if tiers.events.len() > 1 {
+			let order = tiers.order();
+			let mut tried = 0;
+			for &tier in &order {
+				tried += 1;
+				read = self.xreadgroup(&tiers.events[tier], false).await?;
+				if read.is_some() {
+					break;
+				}
+			}
+
+			tiers.record(&order, tried, read.is_some());
+		}
+
+		let read = match read {
+			Some(read) => read,
+			None => self
+				.xreadgroup(tiers.events.concat(), true)
+				.await?
+				.unwrap_or_default(),
+		};
 
-		let messages = read.0.into_iter().flat_map(move |(event, entries)| {
-			entries.0.into_iter().map(move |(id, entry)| {
-				Message::new(id, entry, Bytes::copy_from_slice(&event.0), self.clone())
+		// Entries are unordered in the response, so restore stream order before interleaving the
+		// events round-robin.
+		let mut streams = read
+			.0
+			.into_iter()
+			.map(|(event, entries)| {
+				let mut entries = entries.0.into_iter().collect::<Vec<_>>();
+				entries.sort_unstable_by_key(|(id, _)| (id.0, id.1));
+				(Bytes::copy_from_slice(&event.0), entries.into_iter())
 			})
-		});
+			.collect::<Vec<_>>();
+
+		let mut entries = Vec::new();
+		while !streams.is_empty() {
+			streams.retain_mut(|(event, stream)| match stream.next() {
+				Some((id, entry)) => {
+					entries.push((id, entry, event.clone()));
+					true
+				}
+				None => false,
+			});
+		}
+
+		let mut messages = Vec::with_capacity(entries.len());
+		for (id, entry, event) in entries {
+			messages.extend(self.message(id, entry, event).await?);
+		}
+
+		Ok(messages.into_iter().map(Ok))
+	}
+
+	/// Make a message from a stream entry. Entries that can't be decoded are logged and
+	/// acknowledged, so that they're skipped instead of being claimed over and over.
+	async fn message<D>(
+		&self,
+		id: Id,
+		entry: Entry<'static>,
+		event: Bytes,
+	) -> Result<Option<Message<D>>>
+	where
+		D: DeserializeOwned,
+	{
+		match Message::new(id, entry, event.clone(), self.clone()) {
+			Ok(message) => Ok(Some(message)),
+			Err(err) => {
+				warn!(%err, event = %String::from_utf8_lossy(&event), %id, "Skipping undecodable message");
+				self.xack(&event, &id).await?;
+				Ok(None)
+			}
+		}
+	}
+
+	/// Acknowledge the message `id` for `event`, removing it from the group's pending entries.
+	pub(crate) async fn xack(&self, event: &[u8], id: &Id) -> Result<()> {
+		self.pool
+			.get()
+			.await?
+			.cmd([b"xack", event, &*self.group, id.to_string().as_bytes()])
+			.await?;
 
-		Ok(messages)
+		Ok(())
 	}
 
-	async fn xreadgroup<T, U>(&self, events: T) -> Result<Option<ReadResponse<'static>>, Error>
+	async fn xreadgroup<T, U>(
+		&self,
+		events: T,
+		block: bool,
+	) -> Result<Option<ReadResponse<'static>>, Error>
 	where
 		T: AsRef<[U]>,
 		U: AsRef<[u8]>,
@@ -219,10 +614,11 @@ impl Client {
 			&*self.name,
 			b"COUNT",
 			DEFAULT_MAX_CHUNK,
-			b"BLOCK",
-			DEFAULT_BLOCK_INTERVAL,
-			b"STREAMS",
 		];
+		if block {
+			cmd.extend_from_slice(&[b"BLOCK", DEFAULT_BLOCK_INTERVAL]);
+		}
+		cmd.push(b"STREAMS");
 		cmd.extend(events.iter().map(|b| b.as_ref()));
 		cmd.extend_from_slice(&ids);
 
@@ -255,17 +651,18 @@ impl Client {
 		Ok(res.1)
 	}
 
-	fn autoclaim_all<'s, T, U>(
+	fn autoclaim_all<'s, D, T, U>(
 		&'s self,
 		events: T,
-	) -> impl TryStream<Ok = Message, Error = Error> + 's
+	) -> impl TryStream<Ok = Message<D>, Error = Error> + 's
 	where
+		D: DeserializeOwned + 's,
 		T: AsRef<[U]>,
 		U: AsRef<[u8]>,
 	{
 		let streams = events
 			.as_ref()
-			.into_iter()
+			.iter()
 			.map(|event| {
 				let event = Bytes::copy_from_slice(event.as_ref());
 				move || {
@@ -285,21 +682,28 @@ impl Client {
 	///
 	/// Delays every invocation of `xautoclaim` by [`DEFAULT_BLOCK_DURATION`], since `xautoclaim`
 	/// does not support blocking.
-	async fn autoclaim_event<'s>(
+	async fn autoclaim_event<'s, D>(
 		&'s self,
 		event: Bytes,
-	) -> Result<impl TryStream<Ok = Message, Error = Error> + 's> {
+	) -> Result<impl TryStream<Ok = Message<D>, Error = Error> + 's>
+	where
+		D: DeserializeOwned + 's,
+	{
 		sleep(DEFAULT_BLOCK_DURATION).await;
 
-		let messages = self
-			.xautoclaim(&event)
-			.await?
-			.0
-			.into_iter()
-			.map(move |(id, data)| {
-				Ok::<_, Error>(Message::new(id, data, event.clone(), self.clone()))
-			});
+		let entries = self.xautoclaim(&event).await?.0;
+		let mut messages = Vec::with_capacity(entries.len());
+		for (id, entry) in entries {
+			messages.extend(self.message(id, entry, event.clone()).await?);
+		}
 
-		Ok(iter(messages))
+		Ok(iter(messages.into_iter().map(Ok)))
 	}
 }
+
+/// The PubSub channel that replies to the message `id` in the stream for `event` are sent on.
+pub(crate) fn reply_channel(event: &[u8], id: &Id) -> Vec<u8> {
+	let mut channel = event.to_vec();
+	write!(channel, ":{}", id).unwrap();
+	channel
+}
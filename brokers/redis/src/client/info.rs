@@ -0,0 +1,58 @@
+use redust::resp::Data;
+
+/// The state of the stream of an event, from `XINFO`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StreamInfo {
+	/// The number of entries in the stream.
+	pub length: i64,
+	/// The number of entries delivered to the group but not yet acknowledged.
+	pub pending: i64,
+}
+
+impl StreamInfo {
+	/// Read the replies to `XINFO STREAM` and `XINFO GROUPS` for `group`.
+	pub(crate) fn from_replies(stream: &Data, groups: &Data, group: &[u8]) -> Self {
+		let length = field(stream, "length").and_then(as_integer);
+
+		let pending = match groups {
+			Data::Array(groups) => groups
+				.iter()
+				.find(|info| field(info, "name").and_then(as_bytes) == Some(group))
+				.and_then(|info| field(info, "pending"))
+				.and_then(as_integer),
+			_ => None,
+		};
+
+		Self {
+			length: length.unwrap_or_default(),
+			pending: pending.unwrap_or_default(),
+		}
+	}
+}
+
+/// Find the value of `key` in a reply of alternating keys and values.
+fn field<'a, 'b>(data: &'a Data<'b>, key: &str) -> Option<&'a Data<'b>> {
+	let Data::Array(items) = data else {
+		return None;
+	};
+
+	items
+		.chunks_exact(2)
+		.find(|pair| as_bytes(&pair[0]) == Some(key.as_bytes()))
+		.map(|pair| &pair[1])
+}
+
+fn as_bytes<'a>(data: &'a Data) -> Option<&'a [u8]> {
+	match data {
+		Data::SimpleString(str) => Some(str.as_bytes()),
+		Data::BulkString(bytes) => Some(bytes),
+		_ => None,
+	}
+}
+
+fn as_integer(data: &Data) -> Option<i64> {
+	match data {
+		Data::Integer(int) => Some(*int),
+		_ => None,
+	}
+}
@@ -1,20 +1,22 @@
 use std::{
 	fmt::Debug,
-	io::Write,
 	str::from_utf8,
-	time::{Duration, SystemTime, UNIX_EPOCH},
+	time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use bytes::Bytes;
 use redust::model::stream::{read::Entry, Id};
-use spectacles::{from_slice, Value};
+use serde::{de::DeserializeOwned, Serialize};
+use spectacles::{from_slice, metrics::metrics, to_vec, Value};
 
-use crate::client::{Client, STREAM_DATA_KEY, STREAM_TIMEOUT_KEY};
+use crate::client::{
+	reply_channel, Client, STREAM_DATA_KEY, STREAM_TIMEOUT_KEY, STREAM_TRACEPARENT_KEY,
+};
 
 /// A message received from the broker.
 #[derive(Debug, Clone)]
-pub struct Message {
+pub struct Message<T = Value> {
 	/// The group this message belongs to.
 	pub group: Bytes,
 	/// The event this message signals.
@@ -22,71 +24,93 @@ pub struct Message {
 	/// The ID of this message (generated by Redis).
 	pub id: Id,
 	/// The data of this message. Always present unless there is a bug with a client implementation.
-	pub data: Value,
+	pub data: T,
 	/// When this message times out. Clients should cancel work if it is still in progress after
 	/// this instant.
 	pub timeout_at: Option<SystemTime>,
+	/// The W3C traceparent of the span this message was published from.
+	pub traceparent: Option<String>,
+	received_at: Instant,
 	broker: Client,
 }
 
-impl PartialEq for Message {
+impl<T> PartialEq for Message<T> {
 	fn eq(&self, other: &Self) -> bool {
 		self.id == other.id
 	}
 }
 
-impl Eq for Message {}
+impl<T> Eq for Message<T> {}
 
-impl Message {
-	pub(super) fn new(id: Id, entry: Entry, event: Bytes, broker: Client) -> Self {
-		let data = entry
-			.get(&STREAM_DATA_KEY)
-			.and_then(|value| from_slice(&value.0).ok())
-			.expect("data value missing");
+impl<T> Message<T> {
+	pub(super) fn new(id: Id, entry: Entry, event: Bytes, broker: Client) -> Result<Self>
+	where
+		T: DeserializeOwned,
+	{
+		let data = decode_data(&id, &entry)?;
 
 		let timeout_at = entry
 			.get(&STREAM_TIMEOUT_KEY)
 			.and_then(|value| from_utf8(&value.0).ok()?.parse().ok())
 			.map(|timeout| UNIX_EPOCH + Duration::from_nanos(timeout));
 
-		Message {
+		let traceparent = entry
+			.get(&STREAM_TRACEPARENT_KEY)
+			.and_then(|value| Some(from_utf8(&value.0).ok()?.to_owned()));
+
+		Ok(Message {
 			group: broker.group.clone(),
 			event,
 			id,
 			data,
 			timeout_at,
+			traceparent,
+			received_at: Instant::now(),
 			broker,
-		}
+		})
 	}
 
+	/// Acknowledge this message, removing it from the group's pending entries.
 	pub async fn ack(&self) -> Result<()> {
-		self.broker
-			.pool
-			.get()
-			.await?
-			.cmd([
-				b"xack",
-				&*self.event,
-				&*self.group,
-				self.id.to_string().as_bytes(),
-			])
-			.await?;
+		self.broker.xack(&self.event, &self.id).await?;
 
+		metrics()
+			.ack_latency
+			.observe(self.received_at.elapsed().as_secs_f64());
 		Ok(())
 	}
 
-	/// Reply to this message.
-	pub async fn reply(&self, data: impl AsRef<[u8]>) -> Result<()> {
-		let mut key = self.event.to_vec();
-		write!(key, ":{}", self.id)?;
+	/// Reply to this message. The reply is received by the [`Client::request`] that published it.
+	pub async fn reply<R>(&self, data: &R) -> Result<()>
+	where
+		R: Serialize + ?Sized,
+	{
+		let key = reply_channel(&self.event, &self.id);
 
 		self.broker
 			.pool
 			.get()
 			.await?
-			.cmd([b"publish", key.as_slice(), data.as_ref()])
+			.cmd([b"publish".as_slice(), &key, &to_vec(data)?])
 			.await?;
 
 		Ok(())
 	}
 }
+
+/// Decode the data of the stream entry `id`.
+pub(crate) fn decode_data<T>(id: &Id, entry: &Entry) -> Result<T>
+where
+	T: DeserializeOwned,
+{
+	let data = entry
+		.get(&STREAM_DATA_KEY)
+		.ok_or_else(|| anyhow!("data value missing from message {}", id))
+		.and_then(|value| Ok(from_slice(&value.0)?));
+
+	if data.is_err() {
+		metrics().decode_errors.inc();
+	}
+
+	data
+}
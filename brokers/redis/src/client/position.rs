@@ -0,0 +1,178 @@
+use std::{
+	fmt::{self, Display, Formatter},
+	str::FromStr,
+	time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{anyhow, Error};
+use humantime::{format_rfc3339_millis, parse_rfc3339_weak};
+use redust::model::stream::Id;
+use serde::{Deserialize, Serialize};
+
+/// A position in a stream, used to choose where consumer groups start and which events are
+/// replayed.
+///
+/// Parses from `start` (or `0`), `end` (or `$`), a stream ID such as `1526919030474-55`, or an RFC
+/// 3339 timestamp such as `2022-11-20T12:00:00Z`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum Position {
+	/// The first event in the stream.
+	Start,
+	/// The last event in the stream.
+	#[default]
+	End,
+	/// The event with this ID.
+	Id(Id),
+	/// The first event published at or after this time.
+	Time(SystemTime),
+}
+
+impl Position {
+	/// The ID to pass to `XGROUP CREATE` or `XGROUP SETID` so that the group is delivered every
+	/// event after this position.
+	pub(crate) fn group_id(&self) -> String {
+		match self {
+			Self::Start => "0".to_string(),
+			Self::End => "$".to_string(),
+			Self::Id(id) => id.to_string(),
+			// The group delivers entries after the given ID, so start from just before the time
+			// to include events published at exactly that millisecond.
+			Self::Time(time) => match millis(time) {
+				0 => "0".to_string(),
+				ms => Id(ms - 1, u64::MAX).to_string(),
+			},
+		}
+	}
+
+	/// A bound of an `XRANGE`, inclusive of this position. Redis treats a bare millisecond
+	/// timestamp as the first ID at that time when used as the start, and the last when used as
+	/// the end.
+	pub(crate) fn range_id(&self) -> String {
+		match self {
+			Self::Start => "-".to_string(),
+			Self::End => "+".to_string(),
+			Self::Id(id) => id.to_string(),
+			Self::Time(time) => millis(time).to_string(),
+		}
+	}
+}
+
+fn millis(time: &SystemTime) -> u64 {
+	time.duration_since(UNIX_EPOCH)
+		.unwrap_or_default()
+		.as_millis() as u64
+}
+
+impl Display for Position {
+	fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+		match self {
+			Self::Start => f.write_str("start"),
+			Self::End => f.write_str("end"),
+			Self::Id(id) => id.fmt(f),
+			Self::Time(time) => format_rfc3339_millis(*time).fmt(f),
+		}
+	}
+}
+
+impl FromStr for Position {
+	type Err = Error;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s {
+			"start" | "0" => Ok(Self::Start),
+			"end" | "$" => Ok(Self::End),
+			s if is_id(s) => s
+				.parse()
+				.map(Self::Id)
+				.map_err(|_| anyhow!("invalid stream position: {}", s)),
+			s => parse_rfc3339_weak(s)
+				.map(Self::Time)
+				.map_err(|_| anyhow!("invalid stream position: {}", s)),
+		}
+	}
+}
+
+/// Whether `s` looks like a stream ID, `<ms>-<seq>`. Redust's parser also accepts the start of a
+/// timestamp like `2022-11-20`, so timestamps have to be ruled out first.
+fn is_id(s: &str) -> bool {
+	let digits = |s: &str| !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit());
+	s.split_once('-')
+		.is_some_and(|(ms, seq)| digits(ms) && digits(seq))
+}
+
+impl TryFrom<String> for Position {
+	type Error = Error;
+
+	fn try_from(value: String) -> Result<Self, Self::Error> {
+		value.parse()
+	}
+}
+
+impl From<Position> for String {
+	fn from(position: Position) -> Self {
+		position.to_string()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::time::Duration;
+
+	use super::*;
+
+	fn time(ms: u64) -> Position {
+		Position::Time(UNIX_EPOCH + Duration::from_millis(ms))
+	}
+
+	#[test]
+	fn parse() {
+		assert_eq!("start".parse::<Position>().unwrap(), Position::Start);
+		assert_eq!("0".parse::<Position>().unwrap(), Position::Start);
+		assert_eq!("end".parse::<Position>().unwrap(), Position::End);
+		assert_eq!("$".parse::<Position>().unwrap(), Position::End);
+		assert_eq!(
+			"1526919030474-55".parse::<Position>().unwrap(),
+			Position::Id(Id(1526919030474, 55))
+		);
+		assert_eq!(
+			"2022-11-20T12:00:00Z".parse::<Position>().unwrap(),
+			time(1668945600000)
+		);
+		assert_eq!(
+			"2022-11-20T12:00:00.250Z".parse::<Position>().unwrap(),
+			time(1668945600250)
+		);
+		assert!("yesterday".parse::<Position>().is_err());
+		assert!("".parse::<Position>().is_err());
+	}
+
+	#[test]
+	fn display_round_trips() {
+		for position in [
+			Position::Start,
+			Position::End,
+			Position::Id(Id(1526919030474, 55)),
+			time(1668945600250),
+		] {
+			assert_eq!(position.to_string().parse::<Position>().unwrap(), position);
+		}
+	}
+
+	#[test]
+	fn group_id() {
+		assert_eq!(Position::Start.group_id(), "0");
+		assert_eq!(Position::End.group_id(), "$");
+		assert_eq!(Position::Id(Id(5, 1)).group_id(), "5-1");
+		assert_eq!(time(1000).group_id(), format!("999-{}", u64::MAX));
+		assert_eq!(time(0).group_id(), "0");
+	}
+
+	#[test]
+	fn range_id() {
+		assert_eq!(Position::Start.range_id(), "-");
+		assert_eq!(Position::End.range_id(), "+");
+		assert_eq!(Position::Id(Id(5, 1)).range_id(), "5-1");
+		assert_eq!(time(1000).range_id(), "1000");
+	}
+}
@@ -0,0 +1,135 @@
+use std::{cmp::Reverse, sync::Mutex};
+
+use bytes::Bytes;
+
+/// How many reads in a row a tier can be passed over for higher priority tiers before it's read
+/// first, so that a steady flow of high priority events can't starve lower priorities.
+pub(crate) const MAX_SKIPPED: u32 = 10;
+
+/// Events grouped by priority, in descending order, and how many reads each tier has been passed
+/// over for.
+#[derive(Debug)]
+pub(crate) struct Tiers {
+	pub events: Vec<Vec<Bytes>>,
+	skipped: Mutex<Vec<u32>>,
+}
+
+impl Tiers {
+	/// Group events by priority. Events with the same priority form one tier.
+	pub fn new<I, E>(events: I) -> Self
+	where
+		I: IntoIterator<Item = (E, u32)>,
+		E: AsRef<[u8]>,
+	{
+		let mut events = events
+			.into_iter()
+			.map(|(event, priority)| (Bytes::copy_from_slice(event.as_ref()), priority))
+			.collect::<Vec<_>>();
+		events.sort_by_key(|(_, priority)| Reverse(*priority));
+
+		let tiers = events
+			.chunk_by(|(_, a), (_, b)| a == b)
+			.map(|tier| tier.iter().map(|(event, _)| event.clone()).collect())
+			.collect();
+
+		Self::from_tiers(tiers)
+	}
+
+	pub fn from_tiers(events: Vec<Vec<Bytes>>) -> Self {
+		let skipped = Mutex::new(vec![0; events.len()]);
+		Self { events, skipped }
+	}
+
+	/// The order to try tiers in for the next read: tiers that have been passed over
+	/// [`MAX_SKIPPED`] times first, then the rest, each by priority.
+	pub fn order(&self) -> Vec<usize> {
+		let skipped = self.skipped.lock().unwrap();
+		let mut order = (0..self.events.len()).collect::<Vec<_>>();
+		order.sort_by_key(|&tier| skipped[tier] < MAX_SKIPPED);
+		order
+	}
+
+	/// Record a read that tried the first `tried` tiers of [`Self::order`], the last of which had
+	/// messages if `found`. Lower priority tiers that weren't tried were passed over.
+	pub fn record(&self, order: &[usize], tried: usize, found: bool) {
+		let mut skipped = self.skipped.lock().unwrap();
+		for &tier in &order[..tried] {
+			skipped[tier] = 0;
+		}
+
+		if let (true, Some(&read)) = (found, order[..tried].last()) {
+			for &tier in &order[tried..] {
+				if tier > read {
+					skipped[tier] += 1;
+				}
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn tiers() -> Tiers {
+		Tiers::new([("LOW", 0), ("HIGH", 10), ("MID", 5), ("ALSO_HIGH", 10)])
+	}
+
+	#[test]
+	fn groups_by_priority() {
+		assert_eq!(
+			tiers().events,
+			vec![
+				vec![Bytes::from("HIGH"), Bytes::from("ALSO_HIGH")],
+				vec![Bytes::from("MID")],
+				vec![Bytes::from("LOW")],
+			]
+		);
+	}
+
+	#[test]
+	fn reads_by_priority() {
+		let tiers = tiers();
+		assert_eq!(tiers.order(), [0, 1, 2]);
+
+		// The highest tier is empty, so the next one is read.
+		tiers.record(&[0, 1, 2], 2, true);
+		assert_eq!(tiers.order(), [0, 1, 2]);
+	}
+
+	#[test]
+	fn bounds_starvation() {
+		let tiers = tiers();
+		for _ in 0..MAX_SKIPPED {
+			assert_eq!(tiers.order(), [0, 1, 2]);
+			tiers.record(&[0, 1, 2], 1, true);
+		}
+
+		// Both lower tiers were passed over, so they're tried first.
+		assert_eq!(tiers.order(), [1, 2, 0]);
+		tiers.record(&[1, 2, 0], 1, true);
+		assert_eq!(tiers.order(), [2, 0, 1]);
+		tiers.record(&[2, 0, 1], 1, true);
+		assert_eq!(tiers.order(), [0, 1, 2]);
+	}
+
+	#[test]
+	fn empty_tiers_are_not_starved() {
+		let tiers = tiers();
+		for _ in 0..MAX_SKIPPED {
+			tiers.record(&[0, 1, 2], 1, true);
+		}
+
+		// The starved tiers were empty, so they're no longer tried first.
+		tiers.record(&[1, 2, 0], 3, true);
+		assert_eq!(tiers.order(), [0, 1, 2]);
+	}
+
+	#[test]
+	fn nothing_read() {
+		let tiers = tiers();
+		tiers.record(&[0, 1, 2], 1, true);
+		tiers.record(&[0, 1, 2], 3, false);
+		assert_eq!(tiers.order(), [0, 1, 2]);
+	}
+}
@@ -1,7 +1,9 @@
-use anyhow::Result;
-use clap::Parser;
-use config;
+use std::{net::SocketAddr, str::FromStr};
+
+use anyhow::{anyhow, bail, Error, Result};
+use clap::{Parser, Subcommand, ValueEnum};
 use serde::{Deserialize, Serialize};
+use spectacles_redis::Position;
 
 #[derive(Debug, Serialize, Deserialize, Parser)]
 #[command(name = "spectacles-redis")]
@@ -25,6 +27,105 @@ pub struct Config {
 	#[arg(long, short, env = "REDIS_EVENTS", value_delimiter = ',')]
 	#[serde(default)]
 	pub events: Vec<String>,
+
+	/// Priorities of events to consume, as `EVENT=PRIORITY`. New messages are read from the
+	/// highest priority events first, though events passed over for 10 reads in a row are read
+	/// next; events default to a priority of 0.
+	#[arg(
+		long = "priority",
+		short,
+		env = "REDIS_PRIORITIES",
+		value_delimiter = ','
+	)]
+	#[serde(default)]
+	pub priorities: Vec<Priority>,
+
+	/// How events are transported through Redis.
+	#[arg(long, short, env = "REDIS_MODE", value_enum, default_value_t)]
+	#[serde(default)]
+	pub mode: Mode,
+
+	/// Where newly created consumer groups start reading: `start`, `end`, a stream ID, or an RFC
+	/// 3339 timestamp.
+	#[arg(long, short, env = "REDIS_START", default_value_t)]
+	#[serde(default)]
+	pub start: Position,
+
+	/// Move existing consumer groups to `start` as well, re-reading events after it.
+	#[arg(long, env = "REDIS_RESET_START")]
+	#[serde(default)]
+	pub reset_start: bool,
+
+	/// The address to serve health checks and metrics on.
+	#[arg(long, env = "REDIS_ADMIN_ADDR")]
+	#[serde(default)]
+	pub admin_addr: Option<SocketAddr>,
+
+	#[command(subcommand)]
+	#[serde(skip)]
+	pub command: Option<Command>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Priority {
+	pub event: String,
+	pub priority: u32,
+}
+
+impl FromStr for Priority {
+	type Err = Error;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		let (event, priority) = s
+			.split_once('=')
+			.ok_or_else(|| anyhow!("expected EVENT=PRIORITY, got {}", s))?;
+
+		Ok(Self {
+			event: event.to_string(),
+			priority: priority.parse()?,
+		})
+	}
+}
+
+impl TryFrom<String> for Priority {
+	type Error = Error;
+
+	fn try_from(value: String) -> Result<Self, Self::Error> {
+		value.parse()
+	}
+}
+
+impl From<Priority> for String {
+	fn from(priority: Priority) -> Self {
+		format!("{}={}", priority.event, priority.priority)
+	}
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+	/// Write the events between two positions in each of `events` to STDOUT and exit. Events are
+	/// read outside of the consumer group, so no messages are acknowledged.
+	Replay {
+		/// The position of the first event to replay.
+		#[arg(long, default_value_t = Position::Start)]
+		from: Position,
+
+		/// The position of the last event to replay.
+		#[arg(long, default_value_t = Position::End)]
+		to: Position,
+	},
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Mode {
+	/// Persist events in streams, consumed once per group.
+	#[default]
+	Streams,
+	/// Broadcast events to every subscriber with PubSub. Events are not persisted and `events`
+	/// may contain glob-style patterns.
+	Pubsub,
 }
 
 impl Config {
@@ -35,6 +136,12 @@ impl Config {
 
 impl Config {
 	pub fn build() -> Result<Config> {
+		let config = Self::load()?;
+		config.validate()?;
+		Ok(config)
+	}
+
+	fn load() -> Result<Config> {
 		let opt = Config::parse();
 
 		if let Some(config_file) = opt.config_file {
@@ -43,17 +150,79 @@ impl Config {
 			let env_source = config::Environment::with_prefix("REDIS")
 				.try_parsing(true)
 				.list_separator(",")
-				.with_list_parse_key("events");
+				.with_list_parse_key("events")
+				.with_list_parse_key("priorities");
 
-			let config: Config = config::Config::builder()
+			let mut config: Config = config::Config::builder()
 				.add_source(file_source)
 				.add_source(env_source)
 				.build()?
 				.try_deserialize()?;
 
-			return Ok(config);
+			config.command = opt.command;
+			Ok(config)
 		} else {
 			Ok(opt)
 		}
 	}
+
+	/// Check that priorities are only given to events that are consumed from streams, since they
+	/// would otherwise be silently ignored.
+	fn validate(&self) -> Result<()> {
+		if self.priorities.is_empty() {
+			return Ok(());
+		}
+
+		if self.mode != Mode::Streams {
+			bail!("priorities only apply in streams mode");
+		}
+
+		for priority in &self.priorities {
+			if !self.events.contains(&priority.event) {
+				bail!(
+					"priority given for {}, which isn't in events",
+					priority.event
+				);
+			}
+		}
+
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn config(args: &[&str]) -> Config {
+		Config::parse_from([&["spectacles-redis", "--group", "bot"], args].concat())
+	}
+
+	#[test]
+	fn priority() {
+		let priority = "INTERACTION_CREATE=10".parse::<Priority>().unwrap();
+		assert_eq!(priority.event, "INTERACTION_CREATE");
+		assert_eq!(priority.priority, 10);
+
+		assert!("INTERACTION_CREATE".parse::<Priority>().is_err());
+		assert!("INTERACTION_CREATE=high".parse::<Priority>().is_err());
+	}
+
+	#[test]
+	fn priorities_of_consumed_events() {
+		let config = config(&["--events", "A,B", "--priority", "A=10"]);
+		assert!(config.validate().is_ok());
+	}
+
+	#[test]
+	fn priorities_of_other_events() {
+		let config = config(&["--events", "A,B", "--priority", "C=10"]);
+		assert!(config.validate().is_err());
+	}
+
+	#[test]
+	fn priorities_in_pubsub_mode() {
+		let config = config(&["--events", "A", "--priority", "A=10", "--mode", "pubsub"]);
+		assert!(config.validate().is_err());
+	}
 }
@@ -0,0 +1,37 @@
+//! A Redis Streams broker for Spectacles.
+//!
+//! Events are published to a stream named after the event and consumed through a consumer group,
+//! so that every group receives each event exactly once. Messages that are not acknowledged in
+//! time are automatically claimed by another consumer in the same group.
+//!
+//! ```no_run
+//! use futures::TryStreamExt;
+//! use spectacles_redis::{
+//!     redust::pool::{Manager, Pool},
+//!     Client,
+//! };
+//!
+//! # async fn run() -> anyhow::Result<()> {
+//! let pool = Pool::builder(Manager::new("localhost:6379".to_string())).build()?;
+//! let client = Client::new("my-group", pool);
+//!
+//! let events = ["MESSAGE_CREATE"];
+//! client.ensure_events(events.iter()).await?;
+//! client.publish("MESSAGE_CREATE", &"hello world").await?;
+//!
+//! let mut messages = client.consume::<String, _, _>(events);
+//! while let Some(message) = messages.try_next().await? {
+//!     println!("{}", message.data);
+//!     message.ack().await?;
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+pub use redust;
+
+pub use crate::client::{
+	info::StreamInfo, message::Message, position::Position, repeat_fn, Client,
+};
+
+pub mod client;
@@ -1,46 +1,167 @@
+use std::time::Duration;
+
 use anyhow::Result;
-use futures::{StreamExt, TryStreamExt};
+use futures::{SinkExt, StreamExt, TryStreamExt};
 use redust::pool::{Manager, Pool};
-use spectacles::{init_tracing, io::read, to_vec, AnyEvent, EventRef};
-use tokio::{
-	io::{stdout, AsyncWriteExt},
-	task::JoinSet,
+use spectacles::{
+	admin, init_tracing,
+	io::{read, write},
+	metrics::metrics,
+	prometheus::{register_int_gauge_vec, IntGaugeVec},
+	trace, AnyEvent, EventRef, Value,
 };
+use spectacles_redis::{Client, Position};
+use tokio::{task::JoinSet, time::interval};
+use tracing::{debug, info_span, warn, Instrument};
 
-use crate::client::Client;
-use crate::config::Config;
+use crate::config::{Command, Config, Mode};
 
-mod client;
 mod config;
 
-async fn publish_from_stdin(client: Client) -> Result<()> {
+const HEALTH_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+const STREAM_INFO_INTERVAL: Duration = Duration::from_secs(15);
+
+async fn publish_from_stdin(client: Client, mode: Mode) -> Result<()> {
 	let mut stream = read::<AnyEvent>();
 	while let Some(event) = stream.next().await {
-		client.publish(event.name, &event.data).await?;
+		metrics().event_in(&event.name);
+
+		let span = info_span!("publish", event = %event.name);
+		trace::set_parent(&span, event.traceparent.as_deref());
+
+		async {
+			match mode {
+				Mode::Streams => {
+					let traceparent = trace::forward(event.traceparent.as_deref());
+					client
+						.publish_traced(&event.name, &event.data, traceparent.as_deref())
+						.await?;
+				}
+				// PubSub messages are only the data, so the trace ends here.
+				Mode::Pubsub => client.broadcast(&event.name, &event.data).await?,
+			}
+
+			Ok::<_, anyhow::Error>(())
+		}
+		.instrument(span)
+		.await?;
 	}
 
 	Ok(())
 }
 
-async fn consume_to_stdout(client: Client, events: Vec<String>) -> Result<()> {
-	let mut out = stdout();
-	let mut stream = client.consume(events);
+async fn consume_to_stdout(client: Client, events: Vec<(String, u32)>) -> Result<()> {
+	let mut out = write();
+	let mut stream = client.consume_prioritized::<Value, _, _>(events);
 	while let Some(message) = stream.try_next().await? {
-		out.write_all(&to_vec(&EventRef {
-			data: message.data.clone(),
-			name: &String::from_utf8_lossy(&message.event),
-		})?)
+		let event = String::from_utf8_lossy(&message.event);
+
+		let span = info_span!("consume", event = %event);
+		trace::set_parent(&span, message.traceparent.as_deref());
+
+		async {
+			let traceparent = trace::forward(message.traceparent.as_deref());
+			let event = EventRef {
+				traceparent: traceparent.as_deref(),
+				..EventRef::new(&event, &message.data)
+			};
+
+			out.send(&event).await?;
+			metrics().event_out(event.name);
+
+			message.ack().await
+		}
+		.instrument(span)
 		.await?;
+	}
+
+	Ok(())
+}
+
+async fn subscribe_to_stdout(client: Client, patterns: Vec<String>) -> Result<()> {
+	let mut out = write();
+	let mut stream = client.subscribe::<Value, _, _>(patterns).await?;
+	while let Some(event) = stream.try_next().await? {
+		out.send(&event).await?;
+		metrics().event_out(&event.name);
+	}
 
-		message.ack().await?;
+	Ok(())
+}
+
+async fn replay_to_stdout(
+	client: Client,
+	events: Vec<String>,
+	from: Position,
+	to: Position,
+) -> Result<()> {
+	let mut out = write();
+	for event in events {
+		let mut stream = client.range::<Value>(event.clone(), from, to);
+		while let Some((_, data)) = stream.try_next().await? {
+			out.send(EventRef::new(&event, data)).await?;
+			metrics().event_out(&event);
+		}
 	}
 
 	Ok(())
 }
 
+/// Periodically check the connection to Redis, reporting it as the `redis` component.
+async fn watch_connection(client: Client, period: Duration) {
+	let status = admin::component("redis");
+	let mut interval = interval(period);
+
+	loop {
+		interval.tick().await;
+
+		match client.ping().await {
+			Ok(()) => status.up(),
+			Err(err) => {
+				warn!(%err, "Unable to reach Redis");
+				status.down();
+			}
+		}
+	}
+}
+
+/// Periodically report the length of the stream of each event and its entries pending in the group.
+async fn watch_streams(client: Client, events: Vec<String>, period: Duration) -> Result<()> {
+	let length = register_int_gauge_vec!(
+		"spectacles_redis_stream_length",
+		"Entries in the stream of each event.",
+		&["event"]
+	)?;
+	let pending = register_int_gauge_vec!(
+		"spectacles_redis_pending_entries",
+		"Entries delivered to the group but not yet acknowledged, by event.",
+		&["event"]
+	)?;
+
+	let mut interval = interval(period);
+
+	loop {
+		interval.tick().await;
+
+		for event in &events {
+			match client.stream_info(event).await {
+				Ok(info) => {
+					set(&length, event, info.length);
+					set(&pending, event, info.pending);
+				}
+				Err(err) => debug!(%err, event, "Unable to read stream info"),
+			}
+		}
+	}
+}
+
+fn set(gauge: &IntGaugeVec, event: &str, value: i64) {
+	gauge.with_label_values(&[event]).set(value);
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
-	init_tracing();
+	let _tracing = init_tracing();
 
 	let config = Config::build()?;
 
@@ -48,13 +169,55 @@ async fn main() -> Result<()> {
 	let pool = Pool::builder(manager).build()?;
 	let client = Client::new(config.group, pool);
 
-	client.ensure_events(config.events.iter()).await?;
+	if let Some(addr) = config.admin_addr {
+		tokio::spawn(watch_connection(client.clone(), HEALTH_CHECK_INTERVAL));
+		if config.mode == Mode::Streams && !config.events.is_empty() {
+			let events = config.events.clone();
+			tokio::spawn(watch_streams(client.clone(), events, STREAM_INFO_INTERVAL));
+		}
+		admin::spawn(addr);
+	}
+
+	if let Some(Command::Replay { from, to }) = config.command {
+		return replay_to_stdout(client, config.events, from, to).await;
+	}
+
+	if config.mode == Mode::Streams {
+		client
+			.ensure_events_at(config.events.iter(), config.start)
+			.await?;
+
+		if config.reset_start {
+			client
+				.reset_events(config.events.iter(), config.start)
+				.await?;
+		}
+	}
 
 	let mut set = JoinSet::new();
 
-	set.spawn(publish_from_stdin(client.clone()));
-	if config.events.len() > 0 {
-		set.spawn(consume_to_stdout(client, config.events));
+	set.spawn(publish_from_stdin(client.clone(), config.mode));
+	if !config.events.is_empty() {
+		match config.mode {
+			Mode::Streams => {
+				let events = config
+					.events
+					.into_iter()
+					.map(|event| {
+						let priority = config
+							.priorities
+							.iter()
+							.find(|priority| priority.event == event)
+							.map_or(0, |priority| priority.priority);
+
+						(event, priority)
+					})
+					.collect();
+
+				set.spawn(consume_to_stdout(client, events))
+			}
+			Mode::Pubsub => set.spawn(subscribe_to_stdout(client, config.events)),
+		};
 	}
 
 	while set.join_next().await.is_some() {}
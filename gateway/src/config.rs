@@ -1,4 +1,4 @@
-use std::{num::NonZeroUsize, time::Duration};
+use std::{net::SocketAddr, num::NonZeroUsize, time::Duration};
 
 use serde::{Deserialize, Serialize};
 use twilight_gateway::{EventType, Intents};
@@ -9,6 +9,9 @@ pub struct Config {
 	pub gateway: Gateway,
 	#[serde(default)]
 	pub api: Api,
+	/// The address to serve health checks and metrics on.
+	#[serde(default)]
+	pub admin_addr: Option<SocketAddr>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -20,7 +23,7 @@ pub struct Gateway {
 	pub shards: Shards,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Default, Serialize, Deserialize)]
 #[serde(tag = "type", rename_all = "snake_case")]
 pub enum Shards {
 	Bucket {
@@ -33,15 +36,10 @@ pub enum Shards {
 		to: u64,
 		total: u64,
 	},
+	#[default]
 	Recommended,
 }
 
-impl Default for Shards {
-	fn default() -> Self {
-		Self::Recommended
-	}
-}
-
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Api {
 	#[serde(default)]
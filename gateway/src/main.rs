@@ -1,23 +1,29 @@
+use std::collections::HashMap;
+
 use ::config::Config;
 use anyhow::Result;
-use futures::StreamExt;
-use spectacles::{init_tracing, EventRef};
-use tokio::io::{stdout, AsyncWriteExt};
-use tracing::{debug, info, warn};
+use futures::{SinkExt, StreamExt};
+use spectacles::{admin, init_tracing, io::write, metrics::metrics, trace, EventRef};
+use tracing::{debug, info, info_span, warn, Instrument};
 use twilight_gateway::{
 	stream::{self, ShardEventStream},
-	ConfigBuilder,
+	ConfigBuilder, EventTypeFlags,
 };
 use twilight_http::Client;
-use twilight_model::gateway::event::DispatchEvent;
+use twilight_model::gateway::event::{DispatchEvent, Event};
 
 use crate::config::Shards;
 
 mod config;
 
+/// The events that shard statuses are derived from.
+const STATUS_EVENTS: EventTypeFlags = EventTypeFlags::READY
+	.union(EventTypeFlags::RESUMED)
+	.union(EventTypeFlags::GATEWAY_RECONNECT);
+
 #[tokio::main]
 async fn main() -> Result<()> {
-	init_tracing();
+	let _tracing = init_tracing();
 
 	let config: config::Config = Config::builder()
 		.add_source(::config::File::with_name("gateway"))
@@ -27,6 +33,10 @@ async fn main() -> Result<()> {
 
 	info!("{:?}", config);
 
+	if let Some(addr) = config.admin_addr {
+		admin::spawn(addr);
+	}
+
 	let mut builder = Client::builder();
 
 	if let Some(base) = config.api.base {
@@ -40,9 +50,19 @@ async fn main() -> Result<()> {
 
 	let gw_config = twilight_gateway::Config::new(config.token, config.gateway.intents);
 
+	// The events to output, if not every event. The events that shard statuses are derived from are
+	// always received, and dropped before output unless they're asked for.
+	let requested = config.gateway.events.as_ref().map(|events| {
+		events
+			.iter()
+			.copied()
+			.map(EventTypeFlags::from)
+			.collect::<EventTypeFlags>()
+	});
+
 	let per_shard_config = |_, mut builder: ConfigBuilder| {
-		if let Some(event_types) = config.gateway.events.clone() {
-			builder = builder.event_types(event_types.into_iter().map(Into::into).collect());
+		if let Some(requested) = requested {
+			builder = builder.event_types(requested | STATUS_EVENTS);
 		}
 
 		builder.build()
@@ -63,9 +83,17 @@ async fn main() -> Result<()> {
 		}
 	};
 
+	let statuses = shards
+		.iter()
+		.map(|shard| {
+			let id = shard.id().number();
+			(id, admin::component(format!("shard {}", id)))
+		})
+		.collect::<HashMap<_, _>>();
+
 	let mut stream = ShardEventStream::new(shards.iter_mut());
 
-	let mut out = stdout();
+	let mut out = write();
 	while let Some((shard, event)) = stream.next().await {
 		match event {
 			Ok(event) => {
@@ -73,15 +101,39 @@ async fn main() -> Result<()> {
 
 				debug!(kind = kind.name().unwrap_or("[unknown]"), shard = ?shard.id(), ?event);
 
+				if let Some(latency) = shard.latency().average() {
+					metrics()
+						.shard_latency
+						.with_label_values(&[&shard.id().number().to_string()])
+						.set(latency.as_secs_f64());
+				}
+
+				match (&event, statuses.get(&shard.id().number())) {
+					(Event::Ready(_) | Event::Resumed, Some(status)) => status.up(),
+					(Event::GatewayClose(_) | Event::GatewayReconnect, Some(status)) => {
+						status.down()
+					}
+					_ => (),
+				}
+
+				let is_requested = requested
+					.is_none_or(|requested| requested.contains(EventTypeFlags::from(kind)));
+				if !is_requested {
+					continue;
+				}
+
 				if let Ok(dispatch) = DispatchEvent::try_from(event) {
+					let name = kind.name().unwrap_or_default();
+					let span = info_span!("receive", event = name, shard = shard.id().number());
+					let traceparent = span.in_scope(trace::traceparent);
 					let event = EventRef {
-						name: kind.name().unwrap_or_default(),
-						data: dispatch,
+						traceparent: traceparent.as_deref(),
+						shard: Some(shard.id().number()),
+						..EventRef::new(name, dispatch)
 					};
 
-					let bytes = bson::to_vec(&event)?;
-					out.write_all(&bytes).await?;
-					out.flush().await?;
+					out.send(&event).instrument(span).await?;
+					metrics().event_out(event.name);
 				}
 			}
 			Err(error) => {
@@ -0,0 +1,141 @@
+//! An HTTP server reporting the health of a binary to liveness and readiness probes, and its
+//! [metrics](crate::metrics) to Prometheus.
+//!
+//! Binaries register each of their components with [`component`] and report its status as it
+//! changes. `/livez` responds as long as the process runs, while `/readyz` lists the status of
+//! every component and only responds with `200 OK` once none of them are starting or down.
+//! `/metrics` responds with every registered metric.
+
+use std::{
+	collections::BTreeMap,
+	convert::Infallible,
+	fmt::{self, Display},
+	net::SocketAddr,
+	sync::{Arc, Mutex},
+};
+
+use hyper::{
+	service::{make_service_fn, service_fn},
+	Body, Request, Response, Server, StatusCode,
+};
+use tokio::task::JoinHandle;
+use tracing::{error, info};
+
+use crate::metrics;
+
+static COMPONENTS: Mutex<BTreeMap<Arc<str>, Status>> = Mutex::new(BTreeMap::new());
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+	Starting,
+	Up,
+	Down,
+	/// Finished normally, such as STDIN reaching its end. Doesn't affect readiness.
+	Closed,
+}
+
+impl Display for Status {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.write_str(match self {
+			Self::Starting => "starting",
+			Self::Up => "up",
+			Self::Down => "down",
+			Self::Closed => "closed",
+		})
+	}
+}
+
+/// A part of the binary whose status is reported.
+#[derive(Debug, Clone)]
+pub struct Component {
+	name: Arc<str>,
+}
+
+/// Register a component named `name`, which is starting until its status is set.
+pub fn component(name: impl Into<String>) -> Component {
+	let name = Arc::from(name.into());
+	COMPONENTS
+		.lock()
+		.unwrap()
+		.insert(Arc::clone(&name), Status::Starting);
+
+	Component { name }
+}
+
+impl Component {
+	pub fn set(&self, status: Status) {
+		COMPONENTS
+			.lock()
+			.unwrap()
+			.insert(Arc::clone(&self.name), status);
+	}
+
+	pub fn up(&self) {
+		self.set(Status::Up);
+	}
+
+	pub fn down(&self) {
+		self.set(Status::Down);
+	}
+}
+
+/// Whether no component is starting or down.
+pub fn is_ready() -> bool {
+	COMPONENTS
+		.lock()
+		.unwrap()
+		.values()
+		.all(|status| matches!(status, Status::Up | Status::Closed))
+}
+
+/// Serve the admin endpoints at `addr` in the background, logging an error if the server fails.
+pub fn spawn(addr: SocketAddr) -> JoinHandle<()> {
+	tokio::spawn(async move {
+		if let Err(err) = serve(addr).await {
+			error!(%err, "Admin server failed");
+		}
+	})
+}
+
+/// Serve the admin endpoints at `addr`.
+pub async fn serve(addr: SocketAddr) -> Result<(), hyper::Error> {
+	let server = Server::try_bind(&addr)?;
+	info!("Serving health checks and metrics on {}", addr);
+
+	server
+		.serve(make_service_fn(|_| async {
+			Ok::<_, Infallible>(service_fn(handle))
+		}))
+		.await
+}
+
+async fn handle(request: Request<Body>) -> Result<Response<Body>, Infallible> {
+	let (status, body) = match request.uri().path() {
+		"/livez" => (StatusCode::OK, "ok\n".to_string()),
+		"/readyz" => {
+			let status = if is_ready() {
+				StatusCode::OK
+			} else {
+				StatusCode::SERVICE_UNAVAILABLE
+			};
+
+			(status, report())
+		}
+		"/metrics" => (StatusCode::OK, metrics::encode()),
+		_ => (StatusCode::NOT_FOUND, String::new()),
+	};
+
+	let mut response = Response::new(Body::from(body));
+	*response.status_mut() = status;
+	Ok(response)
+}
+
+/// A line with the status of each component.
+fn report() -> String {
+	COMPONENTS
+		.lock()
+		.unwrap()
+		.iter()
+		.map(|(name, status)| format!("{}: {}\n", name, status))
+		.collect()
+}
@@ -0,0 +1,314 @@
+use std::{
+	fmt::Display,
+	io::{self, ErrorKind},
+	marker::PhantomData,
+};
+
+use bytes::{Buf, BufMut, BytesMut};
+use rmp_serde::from_slice;
+use serde::{de::DeserializeOwned, Serialize};
+use tokio_util::codec::{Decoder, Encoder};
+use tracing::warn;
+
+use crate::{metrics::metrics, to_writer};
+
+/// Decodes MessagePack values from a byte stream, such as STDIN. Values that can't be decoded as
+/// `T` are logged and skipped.
+///
+/// The end of each value is found before it's decoded, scanning only the bytes received since the
+/// last call, so a value received in many chunks is still scanned once.
+#[derive(Debug)]
+pub struct FrameDecoder<T> {
+	value: PhantomData<fn() -> T>,
+	/// How many bytes of the next value have been scanned.
+	scanned: usize,
+	/// How many items of the next value are left to scan, counting those nested in arrays and maps.
+	remaining: u64,
+}
+
+impl<T> FrameDecoder<T> {
+	pub fn new() -> Self {
+		Self {
+			value: PhantomData,
+			scanned: 0,
+			remaining: 1,
+		}
+	}
+
+	/// Start scanning the next value.
+	fn reset(&mut self) {
+		self.scanned = 0;
+		self.remaining = 1;
+	}
+
+	/// Scan `src` for the end of the next value, returning its length once it's complete.
+	fn scan(&mut self, src: &[u8]) -> Result<Option<usize>, u8> {
+		while self.remaining > 0 {
+			let (size, items) = match item(&src[self.scanned..]) {
+				Some(item) => item?,
+				None => return Ok(None),
+			};
+
+			if src.len() - self.scanned < size {
+				return Ok(None);
+			}
+
+			self.scanned += size;
+			self.remaining = self.remaining - 1 + items;
+		}
+
+		Ok(Some(self.scanned))
+	}
+}
+
+impl<T> Default for FrameDecoder<T> {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl<T: DeserializeOwned> Decoder for FrameDecoder<T> {
+	type Item = T;
+	type Error = io::Error;
+
+	fn decode(&mut self, src: &mut BytesMut) -> io::Result<Option<T>> {
+		loop {
+			// Find the end of the next value before decoding it, so that a value that doesn't match
+			// `T` can be skipped as a whole.
+			match self.scan(src) {
+				Ok(Some(len)) => {
+					self.reset();
+					let frame = src.split_to(len);
+					match from_slice(&frame) {
+						Ok(value) => return Ok(Some(value)),
+						Err(err) => invalid(err),
+					}
+				}
+				Ok(None) => return Ok(None),
+				Err(marker) => {
+					invalid(format_args!("invalid MessagePack marker {:#04x}", marker));
+					src.advance(self.scanned + 1);
+					self.reset();
+				}
+			}
+		}
+	}
+
+	fn decode_eof(&mut self, src: &mut BytesMut) -> io::Result<Option<T>> {
+		let value = self.decode(src)?;
+		if value.is_none() && !src.is_empty() {
+			warn!(len = src.len(), "Stream ended in the middle of a value");
+			src.clear();
+			self.reset();
+		}
+
+		Ok(value)
+	}
+}
+
+/// The size of the MessagePack item at the start of `buf`, not counting the items nested in it,
+/// and how many items are nested in it. `None` if `buf` is too short to tell, and the marker if
+/// it's invalid.
+fn item(buf: &[u8]) -> Option<Result<(usize, u64), u8>> {
+	let marker = *buf.first()?;
+	// The big-endian length of `n` bytes following the marker.
+	let len = |n: usize| {
+		let bytes = buf.get(1..1 + n)?;
+		Some(bytes.iter().fold(0, |len, &byte| len << 8 | byte as usize))
+	};
+
+	let item = match marker {
+		0x00..=0x7f | 0xc0 | 0xc2 | 0xc3 | 0xe0..=0xff => (1, 0),
+		0x80..=0x8f => (1, u64::from(marker & 0x0f) * 2),
+		0x90..=0x9f => (1, u64::from(marker & 0x0f)),
+		0xa0..=0xbf => (1 + usize::from(marker & 0x1f), 0),
+		0xc1 => return Some(Err(marker)),
+		// bin and str
+		0xc4 | 0xd9 => (2 + len(1)?, 0),
+		0xc5 | 0xda => (3 + len(2)?, 0),
+		0xc6 | 0xdb => (5 + len(4)?, 0),
+		// ext, with its type after the length
+		0xc7 => (3 + len(1)?, 0),
+		0xc8 => (4 + len(2)?, 0),
+		0xc9 => (6 + len(4)?, 0),
+		// float, uint and int
+		0xca => (5, 0),
+		0xcb => (9, 0),
+		0xcc | 0xd0 => (2, 0),
+		0xcd | 0xd1 => (3, 0),
+		0xce | 0xd2 => (5, 0),
+		0xcf | 0xd3 => (9, 0),
+		// fixext
+		0xd4 => (3, 0),
+		0xd5 => (4, 0),
+		0xd6 => (6, 0),
+		0xd7 => (10, 0),
+		0xd8 => (18, 0),
+		// array and map
+		0xdc => (3, len(2)? as u64),
+		0xdd => (5, len(4)? as u64),
+		0xde => (3, len(2)? as u64 * 2),
+		0xdf => (5, len(4)? as u64 * 2),
+	};
+
+	Some(Ok(item))
+}
+
+fn invalid(err: impl Display) {
+	metrics().decode_errors.inc();
+	warn!(%err);
+}
+
+/// Encodes values as MessagePack, the way [`FrameDecoder`] decodes them.
+#[derive(Debug, Default)]
+pub struct FrameEncoder;
+
+impl<T: Serialize> Encoder<T> for FrameEncoder {
+	type Error = io::Error;
+
+	fn encode(&mut self, item: T, dst: &mut BytesMut) -> io::Result<()> {
+		to_writer(&mut dst.writer(), &item)
+			.map_err(|err| io::Error::new(ErrorKind::InvalidData, err))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::{to_vec, AnyEvent, Event, Value};
+
+	use super::*;
+
+	fn event(name: &str, data: Value) -> AnyEvent {
+		Event {
+			id: Some("1".to_string()),
+			shard: Some(2),
+			..Event::new(name.to_string(), data)
+		}
+	}
+
+	fn events() -> Vec<AnyEvent> {
+		let data = Value::Map(vec![
+			("content".into(), "a".repeat(300).into()),
+			(
+				"ids".into(),
+				Value::Array(vec![u64::MAX.into(), (-1).into(), 1.5.into()]),
+			),
+			("bin".into(), Value::Binary(vec![0; 70_000])),
+			("ext".into(), Value::Ext(1, vec![0; 4])),
+			("nil".into(), Value::Nil),
+		]);
+
+		vec![event("MESSAGE_CREATE", data), event("READY", Value::Nil)]
+	}
+
+	fn names(events: &[AnyEvent]) -> Vec<&str> {
+		events.iter().map(|event| event.name.as_str()).collect()
+	}
+
+	/// Decode `bytes` received in chunks split at `splits`, then the end of the stream.
+	fn decode<T: DeserializeOwned>(bytes: &[u8], splits: &[usize]) -> Vec<T> {
+		let mut decoder = FrameDecoder::new();
+		let mut src = BytesMut::new();
+		let mut values = Vec::new();
+
+		let mut start = 0;
+		for &end in splits.iter().chain([&bytes.len()]) {
+			src.extend_from_slice(&bytes[start..end]);
+			start = end;
+			while let Some(value) = decoder.decode(&mut src).unwrap() {
+				values.push(value);
+			}
+		}
+
+		while let Some(value) = decoder.decode_eof(&mut src).unwrap() {
+			values.push(value);
+		}
+
+		values
+	}
+
+	fn encode(events: &[AnyEvent]) -> Vec<u8> {
+		events
+			.iter()
+			.flat_map(|event| to_vec(event).unwrap())
+			.collect()
+	}
+
+	#[test]
+	fn round_trips() {
+		let events = events();
+		let mut bytes = BytesMut::new();
+		for event in &events {
+			FrameEncoder.encode(event, &mut bytes).unwrap();
+		}
+
+		let decoded = decode::<AnyEvent>(&bytes, &[]);
+		assert_eq!(names(&decoded), ["MESSAGE_CREATE", "READY"]);
+		assert_eq!(decoded[0].data, events[0].data);
+		assert_eq!(decoded[1].id.as_deref(), Some("1"));
+		assert_eq!(decoded[1].shard, Some(2));
+	}
+
+	#[test]
+	fn values_split_at_every_byte() {
+		let events = events()
+			.into_iter()
+			.map(|mut event| {
+				// Keep the value small enough to split at every byte quickly.
+				event.data = Value::Array(vec!["a".repeat(40).into(), 300.into(), Value::Nil]);
+				event
+			})
+			.collect::<Vec<_>>();
+		let bytes = encode(&events);
+
+		for split in 0..=bytes.len() {
+			let decoded = decode::<AnyEvent>(&bytes, &[split]);
+			assert_eq!(names(&decoded), ["MESSAGE_CREATE", "READY"], "{}", split);
+			assert_eq!(decoded[0].data, events[0].data);
+		}
+
+		let every_byte = (0..bytes.len()).collect::<Vec<_>>();
+		assert_eq!(decode::<AnyEvent>(&bytes, &every_byte).len(), 2);
+	}
+
+	#[test]
+	fn values_of_other_types_are_skipped() {
+		let mut bytes = to_vec(&Value::Array(vec![1.into(), "a".into()])).unwrap();
+		bytes.extend(to_vec(&"not an event").unwrap());
+		bytes.extend(encode(&events()));
+
+		let decoded = decode::<AnyEvent>(&bytes, &[3]);
+		assert_eq!(names(&decoded), ["MESSAGE_CREATE", "READY"]);
+	}
+
+	#[test]
+	fn invalid_markers_are_skipped() {
+		let mut bytes = vec![0xc1];
+		bytes.extend(to_vec(&1u32).unwrap());
+		// An array of two values, the second of them invalid.
+		bytes.extend([0x92, 0x01, 0xc1]);
+		bytes.extend(to_vec(&2u32).unwrap());
+
+		assert_eq!(decode::<u32>(&bytes, &[]), [1, 2]);
+	}
+
+	#[test]
+	fn stream_ending_mid_value() {
+		let bytes = encode(&events());
+
+		let decoded = decode::<AnyEvent>(&bytes[..bytes.len() - 1], &[]);
+		assert_eq!(names(&decoded), ["MESSAGE_CREATE"]);
+
+		let mut decoder = FrameDecoder::<AnyEvent>::new();
+		let mut src = BytesMut::from(&bytes[..100]);
+		assert!(decoder.decode_eof(&mut src).unwrap().is_none());
+		assert!(src.is_empty());
+
+		// The decoder starts over after the partial value.
+		src.extend_from_slice(&bytes);
+		assert_eq!(
+			decoder.decode(&mut src).unwrap().unwrap().name,
+			"MESSAGE_CREATE"
+		);
+	}
+}
@@ -1,37 +1,100 @@
-use std::io::{stdin, ErrorKind};
+use std::{
+	io,
+	pin::Pin,
+	task::{ready, Context, Poll},
+};
 
-use futures::Stream;
-use rmp_serde::{decode::Error, from_read};
-use serde::de::DeserializeOwned;
-use tokio::{sync::mpsc, task::spawn_blocking};
-use tokio_stream::wrappers::UnboundedReceiverStream;
+use futures::{stream, Sink, Stream, StreamExt};
+use serde::{de::DeserializeOwned, Serialize};
+use tokio::io::{stdin, stdout, Stdout};
+use tokio_util::codec::{FramedRead, FramedWrite};
 use tracing::warn;
 
-pub fn read<T>() -> impl Stream<Item = T>
-where
-	T: DeserializeOwned + Send + Sync + 'static,
-{
-	let (tx, rx) = mpsc::unbounded_channel();
-
-	spawn_blocking(move || {
-		let mut in_ = stdin();
-		loop {
-			match from_read::<_, T>(&mut in_) {
-				Ok(data) => {
-					if tx.send(data).is_err() {
-						warn!("Read value from STDIN but receiver is closed to receive it");
-						break;
-					}
-				}
-				Err(Error::InvalidDataRead(err)) | Err(Error::InvalidMarkerRead(err))
-					if err.kind() == ErrorKind::UnexpectedEof =>
-				{
-					break
-				}
-				Err(err) => warn!(%err),
-			}
+use crate::{
+	admin::{self, Component, Status},
+	codec::{FrameDecoder, FrameEncoder},
+};
+
+/// The number of bytes read from STDIN at once. Nothing more is read until the values read so far
+/// have been consumed.
+const READ_CAPACITY: usize = 64 * 1024;
+
+/// A sink writing values to STDOUT, flushing after each one that is sent. Reports the status of
+/// STDOUT as the `stdout` component.
+#[derive(Debug)]
+pub struct Writer {
+	frames: FramedWrite<Stdout, FrameEncoder>,
+	status: Component,
+}
+
+impl Writer {
+	/// Mark STDOUT as down if `result` is an error.
+	fn report<T>(&self, result: io::Result<T>) -> io::Result<T> {
+		if let Err(err) = &result {
+			warn!(%err, "Unable to write to STDOUT");
+			self.status.down();
 		}
-	});
 
-	UnboundedReceiverStream::new(rx)
+		result
+	}
+}
+
+impl<T: Serialize> Sink<T> for Writer {
+	type Error = io::Error;
+
+	fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+		let result = ready!(Sink::<T>::poll_ready(Pin::new(&mut self.frames), cx));
+		Poll::Ready(self.report(result))
+	}
+
+	fn start_send(mut self: Pin<&mut Self>, item: T) -> io::Result<()> {
+		let result = Pin::new(&mut self.frames).start_send(item);
+		self.report(result)
+	}
+
+	fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+		let result = ready!(Sink::<T>::poll_flush(Pin::new(&mut self.frames), cx));
+		Poll::Ready(self.report(result))
+	}
+
+	fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+		let result = ready!(Sink::<T>::poll_close(Pin::new(&mut self.frames), cx));
+		Poll::Ready(self.report(result))
+	}
+}
+
+/// Read values from STDIN until it ends. Values are only read as the stream is polled, so reading
+/// stops once the stream is dropped.
+pub fn read<T: DeserializeOwned>() -> impl Stream<Item = T> + Unpin {
+	let status = admin::component("stdin");
+	status.up();
+
+	let mut frames = FramedRead::with_capacity(stdin(), FrameDecoder::new(), READ_CAPACITY);
+	stream::poll_fn(move |cx| {
+		let value = match ready!(frames.poll_next_unpin(cx)) {
+			Some(Ok(value)) => Some(value),
+			Some(Err(err)) => {
+				warn!(%err, "Unable to read from STDIN");
+				status.down();
+				None
+			}
+			None => {
+				status.set(Status::Closed);
+				None
+			}
+		};
+
+		Poll::Ready(value)
+	})
+}
+
+/// Write values to STDOUT.
+pub fn write() -> Writer {
+	let status = admin::component("stdout");
+	status.up();
+
+	Writer {
+		frames: FramedWrite::new(stdout(), FrameEncoder),
+		status,
+	}
 }
@@ -1,32 +1,136 @@
-use std::io::stderr;
+use std::{env, io::stderr};
 
+pub use prometheus;
 pub use rmp_serde::{
-	encode::write_named as to_writer, from_read, from_slice, to_vec_named as to_vec,
+	decode::Error as DecodeError, encode::write_named as to_writer, encode::Error as EncodeError,
+	from_read, from_slice, to_vec_named as to_vec,
 };
 pub use rmpv::{Value, ValueRef};
 use serde::{Deserialize, Serialize};
-use tracing_subscriber::EnvFilter;
+use tokio::runtime::Handle;
+use tracing_subscriber::{filter::LevelFilter, prelude::*, EnvFilter};
 
+pub mod admin;
+pub mod codec;
 pub mod io;
+pub mod metrics;
+pub mod trace;
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct Event<T> {
 	pub name: String,
 	pub data: T,
+	/// Identifies this event, so that replies can refer to it.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub id: Option<String>,
+	/// The ID of the event this event is a reply to.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub reply_to: Option<String>,
+	/// The W3C traceparent of the span this event was sent from. See [`trace`].
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub traceparent: Option<String>,
+	/// The gateway shard this event was received on.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub shard: Option<u64>,
+}
+
+impl<T> Event<T> {
+	pub fn new(name: impl Into<String>, data: T) -> Self {
+		Self {
+			name: name.into(),
+			data,
+			id: None,
+			reply_to: None,
+			traceparent: None,
+			shard: None,
+		}
+	}
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct EventRef<'a, T> {
 	pub name: &'a str,
 	pub data: T,
+	/// Identifies this event, so that replies can refer to it.
+	#[serde(default, borrow, skip_serializing_if = "Option::is_none")]
+	pub id: Option<&'a str>,
+	/// The ID of the event this event is a reply to.
+	#[serde(default, borrow, skip_serializing_if = "Option::is_none")]
+	pub reply_to: Option<&'a str>,
+	/// The W3C traceparent of the span this event was sent from. See [`trace`].
+	#[serde(default, borrow, skip_serializing_if = "Option::is_none")]
+	pub traceparent: Option<&'a str>,
+	/// The gateway shard this event was received on.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub shard: Option<u64>,
+}
+
+impl<'a, T> EventRef<'a, T> {
+	pub fn new(name: &'a str, data: T) -> Self {
+		Self {
+			name,
+			data,
+			id: None,
+			reply_to: None,
+			traceparent: None,
+			shard: None,
+		}
+	}
 }
 
 pub type AnyEvent = Event<Value>;
 pub type AnyEventRef<'a> = EventRef<'a, ValueRef<'a>>;
 
-pub fn init_tracing() {
-	tracing_subscriber::fmt::fmt()
-		.with_writer(stderr)
-		.with_env_filter(EnvFilter::from_default_env())
+/// Log to STDERR, filtered by `RUST_LOG`. When `OTEL_EXPORTER_OTLP_ENDPOINT` is set, spans are
+/// also exported over OTLP until the returned guard is dropped, which requires a Tokio runtime.
+pub fn init_tracing() -> TracingGuard {
+	let otlp = match env::var_os("OTEL_EXPORTER_OTLP_ENDPOINT") {
+		Some(_) if Handle::try_current().is_err() => {
+			Err("OTLP export requires a Tokio runtime".to_string())
+		}
+		Some(_) => trace::otlp_tracer()
+			.map(Some)
+			.map_err(|err| err.to_string()),
+		None => Ok(None),
+	};
+
+	let (tracer, error) = match otlp {
+		Ok(tracer) => (tracer, None),
+		Err(err) => (None, Some(err)),
+	};
+	let exporting = tracer.is_some();
+
+	tracing_subscriber::registry()
+		.with(
+			tracing_subscriber::fmt::layer()
+				.with_writer(stderr)
+				.with_filter(EnvFilter::from_default_env()),
+		)
+		.with(tracer.map(|tracer| {
+			tracing_opentelemetry::layer()
+				.with_tracer(tracer)
+				.with_filter(LevelFilter::INFO)
+		}))
 		.init();
+
+	if let Some(err) = error {
+		tracing::warn!(%err, "Unable to export traces");
+	}
+
+	TracingGuard { exporting }
+}
+
+/// Flushes exported spans when dropped.
+#[must_use]
+#[derive(Debug)]
+pub struct TracingGuard {
+	exporting: bool,
+}
+
+impl Drop for TracingGuard {
+	fn drop(&mut self) {
+		if self.exporting {
+			opentelemetry::global::shutdown_tracer_provider();
+		}
+	}
 }
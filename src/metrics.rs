@@ -0,0 +1,155 @@
+//! Prometheus metrics shared by the gateway and brokers, served at `/metrics` by the
+//! [admin](crate::admin) server.
+//!
+//! Binaries may register metrics of their own in [`prometheus::default_registry`], which is
+//! where these are registered.
+
+use std::{
+	collections::HashSet,
+	sync::{Mutex, OnceLock},
+};
+
+use prometheus::{
+	register_gauge_vec, register_histogram, register_int_counter, register_int_counter_vec,
+	register_int_gauge_vec, Encoder, GaugeVec, Histogram, IntCounter, IntCounterVec, IntGaugeVec,
+	TextEncoder,
+};
+
+#[derive(Debug)]
+pub struct Metrics {
+	/// Events read from STDIN, by event name. See [`EventLabels`] for how names are bounded.
+	pub events_in: IntCounterVec,
+	/// Events written to STDOUT, by event name.
+	pub events_out: IntCounterVec,
+	/// Values that couldn't be decoded.
+	pub decode_errors: IntCounter,
+	/// How long it takes to acknowledge a message after receiving it.
+	pub ack_latency: Histogram,
+	/// Work waiting to complete, by queue.
+	pub pending: IntGaugeVec,
+	/// The most work that can be in progress at once, by queue.
+	pub capacity: IntGaugeVec,
+	/// The average heartbeat latency of each gateway shard.
+	pub shard_latency: GaugeVec,
+	event_labels: EventLabels,
+}
+
+/// The shared metrics, registered on first use.
+pub fn metrics() -> &'static Metrics {
+	static METRICS: OnceLock<Metrics> = OnceLock::new();
+
+	METRICS.get_or_init(|| Metrics {
+		events_in: register_int_counter_vec!(
+			"spectacles_events_in_total",
+			"Events read from STDIN.",
+			&["event"]
+		)
+		.unwrap(),
+		events_out: register_int_counter_vec!(
+			"spectacles_events_out_total",
+			"Events written to STDOUT.",
+			&["event"]
+		)
+		.unwrap(),
+		decode_errors: register_int_counter!(
+			"spectacles_decode_errors_total",
+			"Values that couldn't be decoded."
+		)
+		.unwrap(),
+		ack_latency: register_histogram!(
+			"spectacles_ack_latency_seconds",
+			"Time from receiving a message to acknowledging it."
+		)
+		.unwrap(),
+		pending: register_int_gauge_vec!(
+			"spectacles_pending",
+			"Work waiting to complete.",
+			&["queue"]
+		)
+		.unwrap(),
+		capacity: register_int_gauge_vec!(
+			"spectacles_capacity",
+			"The most work that can be in progress at once.",
+			&["queue"]
+		)
+		.unwrap(),
+		shard_latency: register_gauge_vec!(
+			"spectacles_shard_latency_seconds",
+			"Average heartbeat latency of each gateway shard.",
+			&["shard"]
+		)
+		.unwrap(),
+		event_labels: EventLabels::default(),
+	})
+}
+
+impl Metrics {
+	pub fn event_in(&self, name: &str) {
+		let label = self.event_labels.get(name);
+		self.events_in.with_label_values(&[label]).inc();
+	}
+
+	pub fn event_out(&self, name: &str) {
+		let label = self.event_labels.get(name);
+		self.events_out.with_label_values(&[label]).inc();
+	}
+}
+
+/// The most event names that are used as labels. Event names can come from untrusted input, such
+/// as the path of an HTTP request, so they're bounded to keep the number of series in check.
+const MAX_EVENT_LABELS: usize = 256;
+
+/// The label of events whose names aren't used as labels.
+const OTHER_EVENT_LABEL: &str = "other";
+
+/// The event names used as labels: the first [`MAX_EVENT_LABELS`] seen, after which other events
+/// are counted as `other`.
+#[derive(Debug, Default)]
+pub struct EventLabels {
+	names: Mutex<HashSet<Box<str>>>,
+}
+
+impl EventLabels {
+	/// The label to count the event `name` under.
+	pub fn get<'a>(&self, name: &'a str) -> &'a str {
+		let mut names = self.names.lock().unwrap();
+		if names.contains(name) {
+			return name;
+		}
+
+		if names.len() < MAX_EVENT_LABELS {
+			names.insert(name.into());
+			name
+		} else {
+			OTHER_EVENT_LABEL
+		}
+	}
+}
+
+/// Every registered metric, in the Prometheus text format.
+pub fn encode() -> String {
+	let mut buf = Vec::new();
+	TextEncoder::new()
+		.encode(&prometheus::gather(), &mut buf)
+		.expect("metrics can be encoded");
+
+	String::from_utf8(buf).expect("metrics are valid UTF-8")
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn event_labels_are_bounded() {
+		let labels = EventLabels::default();
+		for i in 0..MAX_EVENT_LABELS {
+			let name = format!("EVENT_{}", i);
+			assert_eq!(labels.get(&name), name);
+		}
+
+		assert_eq!(labels.get("ONE_TOO_MANY"), OTHER_EVENT_LABEL);
+		assert_eq!(labels.get("EVENT_0"), "EVENT_0");
+		assert_eq!(labels.get("ONE_TOO_MANY"), OTHER_EVENT_LABEL);
+	}
+}
@@ -0,0 +1,124 @@
+//! Tracing across process boundaries.
+//!
+//! Spans are exported over OTLP when `OTEL_EXPORTER_OTLP_ENDPOINT` is set (see
+//! [`init_tracing`](crate::init_tracing)). The trace continues from one binary to the next through
+//! the W3C `traceparent` of each [`Event`](crate::Event): binaries start a span for each event with
+//! [`set_parent`], and send [`forward`] along with whatever they produce from it.
+
+use std::{collections::HashMap, env, path::Path};
+
+use opentelemetry::{propagation::TextMapPropagator, trace::TraceError, KeyValue};
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::{propagation::TraceContextPropagator, runtime, trace::Tracer, Resource};
+use tracing::Span;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+/// The name of the traceparent in headers and other key-value carriers.
+pub const TRACEPARENT: &str = "traceparent";
+
+/// The traceparent of the current span, if it is exported.
+pub fn traceparent() -> Option<String> {
+	let mut carrier = HashMap::new();
+	TraceContextPropagator::new().inject_context(&Span::current().context(), &mut carrier);
+
+	carrier.remove(TRACEPARENT)
+}
+
+/// Continue the trace of `traceparent` in `span`.
+pub fn set_parent(span: &Span, traceparent: Option<&str>) {
+	if let Some(traceparent) = traceparent {
+		let carrier = HashMap::from([(TRACEPARENT.to_string(), traceparent.to_string())]);
+		span.set_parent(TraceContextPropagator::new().extract(&carrier));
+	}
+}
+
+/// The traceparent to send on from the current span. Binaries that don't export spans pass on
+/// `incoming` unchanged, so that the trace doesn't refer to spans that were never exported.
+pub fn forward(incoming: Option<&str>) -> Option<String> {
+	traceparent().or_else(|| incoming.map(Into::into))
+}
+
+/// A tracer exporting spans over OTLP, configured by the standard `OTEL_*` environment variables.
+/// Must be called within a Tokio runtime.
+pub(crate) fn otlp_tracer() -> Result<Tracer, TraceError> {
+	opentelemetry_otlp::new_pipeline()
+		.tracing()
+		.with_exporter(opentelemetry_otlp::new_exporter().tonic().with_env())
+		.with_trace_config(
+			opentelemetry_sdk::trace::config().with_resource(Resource::new([KeyValue::new(
+				"service.name",
+				service_name(),
+			)])),
+		)
+		.install_batch(runtime::Tokio)
+}
+
+/// `OTEL_SERVICE_NAME`, or the name of the executable.
+fn service_name() -> String {
+	env::var("OTEL_SERVICE_NAME")
+		.ok()
+		.or_else(|| {
+			let exe = env::args().next()?;
+			Some(Path::new(&exe).file_name()?.to_string_lossy().into_owned())
+		})
+		.unwrap_or_else(|| "spectacles".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+	use opentelemetry::trace::TracerProvider as _;
+	use opentelemetry_sdk::trace::TracerProvider;
+	use tracing::info_span;
+	use tracing_subscriber::{layer::SubscriberExt, Registry};
+
+	use super::*;
+
+	const TRACEPARENT: &str = "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01";
+
+	/// Run `f` with spans recorded by OpenTelemetry, as when they're exported.
+	fn exported<T>(f: impl FnOnce() -> T) -> T {
+		// Tracers only hold a weak reference to their provider, so it has to outlive them.
+		let provider = TracerProvider::builder().build();
+		let tracer = provider.tracer("test");
+		let subscriber =
+			Registry::default().with(tracing_opentelemetry::layer().with_tracer(tracer));
+		tracing::subscriber::with_default(subscriber, f)
+	}
+
+	/// The trace ID, span ID and flags of a traceparent.
+	fn parts(traceparent: &str) -> Vec<&str> {
+		traceparent.split('-').collect()
+	}
+
+	#[test]
+	fn forward_continues_the_trace() {
+		let forwarded = exported(|| {
+			let span = info_span!("receive");
+			set_parent(&span, Some(TRACEPARENT));
+			span.in_scope(|| forward(Some(TRACEPARENT)))
+		})
+		.unwrap();
+
+		let (incoming, forwarded) = (parts(TRACEPARENT), parts(&forwarded));
+		assert_eq!(forwarded.len(), 4);
+		assert_eq!(forwarded[0], incoming[0]);
+		assert_eq!(forwarded[1], incoming[1], "the trace ID is kept");
+		assert_ne!(forwarded[2], incoming[2], "the span ID is the new span's");
+	}
+
+	#[test]
+	fn forward_without_parent_starts_a_trace() {
+		let forwarded = exported(|| info_span!("receive").in_scope(|| forward(None))).unwrap();
+		assert_ne!(parts(&forwarded)[1], parts(TRACEPARENT)[1]);
+	}
+
+	#[test]
+	fn forward_unchanged_when_not_exported() {
+		let span = info_span!("receive");
+		set_parent(&span, Some(TRACEPARENT));
+		let forwarded = span.in_scope(|| forward(Some(TRACEPARENT)));
+
+		assert_eq!(forwarded.as_deref(), Some(TRACEPARENT));
+		assert_eq!(span.in_scope(|| forward(None)), None);
+	}
+}
@@ -9,10 +9,7 @@ use spectacles::{to_vec, Event};
 
 fn send_output(mut out: impl Write) {
 	let data = Faker.fake::<HashMap<String, String>>();
-	let event = Event {
-		data,
-		name: "test".to_string(),
-	};
+	let event = Event::new("test", data);
 
 	out.write_all(&to_vec(&event).unwrap()).unwrap();
 }